@@ -0,0 +1,89 @@
+use super::simulator::*;
+use super::sweep::SweepValues;
+use nalgebra::base::{DMatrix, DVector};
+use nalgebra::Complex;
+use serde::*;
+use std::collections::BTreeMap;
+
+// AC 解析の方程式 A x = z (複素数)
+//   ・A は動作点で線形化した方程式の A から始め、周波数によって変わる項を回路素子が書き換える.
+//   ・z には独立電源の AC 振幅・位相を励振として押す.
+pub struct AcEquation {
+    pub frequency: f64,
+    pub a: DMatrix<Complex<f64>>,
+    pub z: DVector<Complex<f64>>,
+}
+
+impl AcEquation {
+    pub fn add_z(&mut self, row: Option<usize>, value: Complex<f64>) {
+        if let Some(row) = row {
+            self.z[row] += value;
+        }
+    }
+}
+
+// 振幅と位相 [度] から複素数を作る
+pub fn phasor(magnitude: f32, phase: f32) -> Complex<f64> {
+    let (magnitude, phase) = (magnitude as f64, (phase as f64).to_radians());
+    Complex::new(magnitude * phase.cos(), magnitude * phase.sin())
+}
+
+// 振幅と位相 [度]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct Phasor {
+    pub magnitude: f32,
+    pub phase: f32,
+}
+
+impl Phasor {
+    fn new(value: Complex<f64>) -> Phasor {
+        Phasor {
+            magnitude: value.re.hypot(value.im) as f32,
+            phase: value.im.atan2(value.re).to_degrees() as f32,
+        }
+    }
+}
+
+// 1 つの周波数での各ノードの電圧
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct AcPoint {
+    pub frequency: f32,
+    pub nodes: BTreeMap<NodeId, Phasor>,
+}
+
+impl Simulator {
+    // 小信号 AC 解析 (SPICE の .AC に相当する)
+    //   ・最後に求めた動作点で線形化し、src_set_ac で設定した独立電源の振幅・位相を励振として解く.
+    //   ・容量やインダクタの素子はまだないので、周波数特性はオペアンプの利得帯域幅積によるものだけ.
+    pub fn ac_analysis(&self, frequencies: &SweepValues) -> Result<Vec<AcPoint>, String> {
+        let eq = self
+            .equation
+            .as_ref()
+            .ok_or("no state calculated".to_string())?;
+        let dim = eq.x.len();
+
+        let mut points = vec![];
+        for frequency in frequencies.values()? {
+            let mut ac = AcEquation {
+                frequency: frequency as f64,
+                a: eq.a.map(|v| Complex::new(v as f64, 0.0)),
+                z: DVector::zeros(dim),
+            };
+            for element in self.elements.values() {
+                element.borrow().stamp_ac(eq, &mut ac);
+            }
+            let x =
+                ac.a.lu()
+                    .solve(&ac.z)
+                    .ok_or("singular matrix".to_string())?;
+            let nodes = self
+                .nodes
+                .iter()
+                .filter(|node_id| **node_id != 0)
+                .map(|node_id| (*node_id, Phasor::new(x[eq.node(*node_id).unwrap()])))
+                .collect();
+            points.push(AcPoint { frequency, nodes });
+        }
+        Ok(points)
+    }
+}
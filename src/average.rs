@@ -0,0 +1,129 @@
+use super::simulator::*;
+use serde::*;
+use std::collections::{BTreeMap, VecDeque};
+
+// PWM などで高速に変化する出力を、直近の一定時間（窓）で時間平均する.
+//   ・状態は次に変化するまで一定なので、変化した時刻とその時の値だけを保持する.
+//   ・analogWrite の 490Hz 程度の PWM であれば窓は 10ms 程度あれば十分.
+pub struct Averager {
+    // 窓の幅 [クロック数]
+    window: u64,
+    samples: VecDeque<Sample>,
+}
+
+// ある時刻から次の Sample までの間の値
+struct Sample {
+    cycle: u64,
+    voltages: BTreeMap<NodeId, f32>,
+    currents: BTreeMap<ElementId, Vec<f32>>,
+    levels: BTreeMap<ElementId, Vec<bool>>,
+}
+
+// 時間平均した回路の状態
+#[derive(Debug, Default, PartialEq, Clone, Serialize)]
+pub struct Average {
+    // 各ノードの電圧の平均
+    pub voltages: BTreeMap<NodeId, f32>,
+    // 各回路素子の端子に流れ込む電流の平均
+    pub currents: BTreeMap<ElementId, Vec<f32>>,
+    // MCU の各ピンが High だった時間の割合
+    pub duty_cycles: BTreeMap<ElementId, Vec<f32>>,
+}
+
+impl Averager {
+    pub fn new(window: u64) -> Averager {
+        Averager {
+            window: window.max(1),
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    // cycle 以降の値を記録する
+    pub fn push(
+        &mut self,
+        cycle: u64,
+        voltages: BTreeMap<NodeId, f32>,
+        currents: BTreeMap<ElementId, Vec<f32>>,
+        levels: BTreeMap<ElementId, Vec<bool>>,
+    ) {
+        // 同じ時刻の値は上書きする
+        if self.samples.back().map(|s| s.cycle) == Some(cycle) {
+            self.samples.pop_back();
+        }
+        self.samples.push_back(Sample {
+            cycle,
+            voltages,
+            currents,
+            levels,
+        });
+
+        // 窓から外れた Sample を捨てる. 窓の先頭の値を決める Sample は残す.
+        let start = cycle.saturating_sub(self.window);
+        while self.samples.len() > 1 && self.samples[1].cycle <= start {
+            self.samples.pop_front();
+        }
+    }
+
+    // 時刻 now における時間平均
+    pub fn average(&self, now: u64) -> Option<Average> {
+        let first = self.samples.front()?;
+        let start = now.saturating_sub(self.window).max(first.cycle);
+
+        // 記録した直後は窓の幅が 0 なので、その時点の値をそのまま返す.
+        let total = now.saturating_sub(start);
+        if total == 0 {
+            let last = self.samples.back()?;
+            return Some(Average {
+                voltages: last.voltages.clone(),
+                currents: last.currents.clone(),
+                duty_cycles: last
+                    .levels
+                    .iter()
+                    .map(|(id, levels)| {
+                        let duty = levels.iter().map(|l| if *l { 1.0 } else { 0.0 }).collect();
+                        (*id, duty)
+                    })
+                    .collect(),
+            });
+        }
+
+        let mut average = Average::default();
+        for (i, sample) in self.samples.iter().enumerate() {
+            let end = self.samples.get(i + 1).map_or(now, |s| s.cycle).min(now);
+            let begin = sample.cycle.max(start);
+            if end <= begin {
+                continue;
+            }
+            let weight = (end - begin) as f32 / total as f32;
+
+            for (node_id, v) in sample.voltages.iter() {
+                *average.voltages.entry(*node_id).or_insert(0.0) += v * weight;
+            }
+            for (element_id, currents) in sample.currents.iter() {
+                let sum = average
+                    .currents
+                    .entry(*element_id)
+                    .or_insert(vec![0.0; currents.len()]);
+                for (s, i) in sum.iter_mut().zip(currents.iter()) {
+                    *s += i * weight;
+                }
+            }
+            for (element_id, levels) in sample.levels.iter() {
+                let sum = average
+                    .duty_cycles
+                    .entry(*element_id)
+                    .or_insert(vec![0.0; levels.len()]);
+                for (s, l) in sum.iter_mut().zip(levels.iter()) {
+                    if *l {
+                        *s += weight;
+                    }
+                }
+            }
+        }
+        Some(average)
+    }
+}
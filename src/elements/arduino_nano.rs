@@ -0,0 +1,52 @@
+use super::super::simulator::*;
+use super::mcu::*;
+use avr_emulator::arch::atmega328p::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// ArduinoNano (ATmega328P)
+//   ・ArduinoUno のピンに加えて、アナログ入力専用の A6, A7 を持つ.
+//   ・コアは PDIP28 として扱うので、ピンの対応は ArduinoUno と同じ.
+pub static ARDUINO_NANO: Board = Board {
+    name: "ArduinoNano",
+    pin_names: &[
+        "D0", "D1", "D2", "D3", "D4", "D5", "D6", "D7", "D8", "D9", "D10", "D11", "D12", "D13",
+        "A0", "A1", "A2", "A3", "A4", "A5", "A6", "A7",
+    ],
+    core_pins: &[
+        Some(1),  // D0: PD0
+        Some(2),  // D1: PD1
+        Some(3),  // D2: PD2
+        Some(4),  // D3: PD3
+        Some(5),  // D4: PD4
+        Some(10), // D5: PD5
+        Some(11), // D6: PD6
+        Some(12), // D7: PD7
+        Some(13), // D8: PB0
+        Some(14), // D9: PB1
+        Some(15), // D10: PB2
+        Some(16), // D11: PB3
+        Some(17), // D12: PB4
+        Some(18), // D13: PB5
+        Some(22), // A0: PC0
+        Some(23), // A1: PC1
+        Some(24), // A2: PC2
+        Some(25), // A3: PC3
+        Some(26), // A4: PC4
+        Some(27), // A5: PC5
+        None,     // A6: ADC6 (TQFP のみ)
+        None,     // A7: ADC7 (TQFP のみ)
+    ],
+    voltage: 5.0,
+};
+
+impl Simulator {
+    pub fn add_arduino_nano(&mut self) -> usize {
+        let core = Rc::new(RefCell::new(ATmega328P::new(Package::PDIP28)));
+        self.add_mcu(&ARDUINO_NANO, core)
+    }
+
+    pub fn arduino_nano_program(&mut self, element_id: usize, hex: String) {
+        self.mcu_program(element_id, &ARDUINO_NANO, hex);
+    }
+}
@@ -1,94 +1,49 @@
 use super::super::simulator::*;
-use super::element::*;
+use super::mcu::*;
 use avr_emulator::arch::atmega328p::*;
-use avr_emulator::avrmcu::AVRMCU;
-use std::any::Any;
 use std::cell::RefCell;
 use std::rc::Rc;
-use wasm_bindgen::prelude::*;
 
-pub struct ArduinoUno {
-    id: usize,
-    pins: [usize; 20],
-    outputs: [bool; 20],
-    voltage: f32,
-    avr: Rc<RefCell<ATmega328P>>,
-}
-
-impl ArduinoUno {
-    pub fn new(id: usize) -> ArduinoUno {
-        ArduinoUno {
-            id: id,
-            pins: [0; 20],
-            outputs: [false; 20],
-            voltage: 5.0,
-            avr: Rc::new(RefCell::new(ATmega328P::new(Package::PDIP28))),
-        }
-    }
-
-    pub fn program(&self, hex: String) {
-        self.avr.borrow().program(hex);
-        self.avr.borrow_mut().initialize();
-    }
-}
-
-impl Element for ArduinoUno {
-    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
-        self.pins[pin_id] = node_id;
-        // ノードに接続したピンは、一旦全て出力ピンとする
-        self.outputs[pin_id] = true;
-    }
-
-    fn output_pins(&self) -> Vec<bool> {
-        self.outputs.to_vec()
-    }
-
-    fn stamp(&self, eq: &mut Equation) {
-        let pin_state = self.avr.borrow().get_pins();
-        for (pin_id, is_output) in self.output_pins().iter().enumerate() {
-            if *is_output && pin_state[pin_id] {
-                let src_index = eq.src_index.get(&(self.id, pin_id)).unwrap() + eq.node_index.len();
-
-                eq.z[src_index] = self.voltage;
-
-                let node_index = *eq.node_index.get(&self.pins[pin_id]).unwrap();
-                eq.a[(node_index, src_index)] = 1.0;
-                eq.a[(src_index, node_index)] = 1.0;
-            }
-        }
-    }
-
-    fn clk(&self) -> bool {
-        let mut avr = self.avr.borrow_mut();
-        let pins = avr.get_pins();
-        avr.next();
-        pins != avr.get_pins()
-    }
-
-    fn as_any(&mut self) -> &mut dyn Any {
-        self
-    }
-}
+// ArduinoUno (ATmega328P, PDIP28)
+//   ・コアのピン番号は PDIP28 のピン番号 - 1. D13 (PB5) は 19 番ピンなので 18 になる.
+pub static ARDUINO_UNO: Board = Board {
+    name: "ArduinoUno",
+    pin_names: &[
+        "D0", "D1", "D2", "D3", "D4", "D5", "D6", "D7", "D8", "D9", "D10", "D11", "D12", "D13",
+        "A0", "A1", "A2", "A3", "A4", "A5",
+    ],
+    core_pins: &[
+        Some(1),  // D0: PD0
+        Some(2),  // D1: PD1
+        Some(3),  // D2: PD2
+        Some(4),  // D3: PD3
+        Some(5),  // D4: PD4
+        Some(10), // D5: PD5
+        Some(11), // D6: PD6
+        Some(12), // D7: PD7
+        Some(13), // D8: PB0
+        Some(14), // D9: PB1
+        Some(15), // D10: PB2
+        Some(16), // D11: PB3
+        Some(17), // D12: PB4
+        Some(18), // D13: PB5
+        Some(22), // A0: PC0
+        Some(23), // A1: PC1
+        Some(24), // A2: PC2
+        Some(25), // A3: PC3
+        Some(26), // A4: PC4
+        Some(27), // A5: PC5
+    ],
+    voltage: 5.0,
+};
 
 impl Simulator {
     pub fn add_arduino_uno(&mut self) -> usize {
-        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
-        let element = Rc::new(RefCell::new(ArduinoUno::new(id)));
-        self.elements.insert(id, element);
-        id
+        let core = Rc::new(RefCell::new(ATmega328P::new(Package::PDIP28)));
+        self.add_mcu(&ARDUINO_UNO, core)
     }
 
     pub fn arduino_uno_program(&mut self, element_id: usize, hex: String) {
-        match self
-            .elements
-            .get(&element_id)
-            .unwrap()
-            .borrow_mut()
-            .as_any()
-            .downcast_mut::<ArduinoUno>()
-        {
-            Some(arduino) => arduino.program(hex),
-            None => panic!("is not ArduinoUno"),
-        }
+        self.mcu_program(element_id, &ARDUINO_UNO, hex);
     }
 }
@@ -0,0 +1,267 @@
+use super::super::simulator::*;
+use super::element::*;
+use serde::*;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+// 開放電圧をこれ以上変化させたら方程式を解き直す [V]
+//   ・放電による変化はクロックごとにはごくわずかなので、毎クロック解き直さない.
+const VOLTAGE_RESOLUTION: f32 = 0.001;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum BatteryChemistry {
+    // 単三アルカリ乾電池
+    AlkalineAa,
+    // リチウムイオン電池 (18650)
+    LiIon,
+    // コイン電池 (CR2032)
+    CoinCell,
+}
+
+// 電池
+//   ・内部抵抗 r を持つ電圧源としてモデリングする. V = Voc(soc) - r * I
+//   ・開放電圧 Voc は充電率 soc (0.0 - 1.0) に対する区分線形の表から求める.
+//   ・流した電流を時間で積分して残量を減らす.
+#[derive(Debug)]
+pub struct Battery {
+    id: usize,
+    // pins[0]: +,  pins[1]: -
+    pins: [usize; 2],
+    outputs: [bool; 2],
+    // (soc, Voc) の表. soc の昇順.
+    ocv: &'static [(f32, f32)],
+    r: f32,
+    // 容量 [C]
+    capacity: f64,
+    // 残量 [C]
+    charge: Cell<f64>,
+    // 最後に解いた時の放電電流 [A]
+    current: Cell<f32>,
+    // 方程式に使っている開放電圧
+    voltage: Cell<f32>,
+    // save した時点の (残量, 放電電流, 開放電圧)
+    saved: Option<(f64, f32, f32)>,
+}
+
+const ALKALINE_AA_OCV: [(f32, f32); 5] = [
+    (0.0, 0.9),
+    (0.1, 1.1),
+    (0.5, 1.25),
+    (0.9, 1.45),
+    (1.0, 1.58),
+];
+const LI_ION_OCV: [(f32, f32); 6] = [
+    (0.0, 3.0),
+    (0.1, 3.45),
+    (0.2, 3.6),
+    (0.5, 3.75),
+    (0.8, 3.95),
+    (1.0, 4.2),
+];
+const COIN_CELL_OCV: [(f32, f32); 5] = [(0.0, 2.0), (0.1, 2.7), (0.5, 2.9), (0.9, 3.0), (1.0, 3.2)];
+
+impl Battery {
+    pub fn new(id: usize, chemistry: BatteryChemistry) -> Battery {
+        // 容量 [mAh], 内部抵抗 [Ω]
+        let (ocv, capacity, r): (&'static [(f32, f32)], f64, f32) = match chemistry {
+            BatteryChemistry::AlkalineAa => (&ALKALINE_AA_OCV, 2500.0, 0.15),
+            BatteryChemistry::LiIon => (&LI_ION_OCV, 2600.0, 0.05),
+            BatteryChemistry::CoinCell => (&COIN_CELL_OCV, 225.0, 15.0),
+        };
+        let capacity = mah_to_coulomb(capacity);
+        let battery = Battery {
+            id,
+            pins: [0, 0],
+            outputs: [true, false],
+            ocv,
+            r,
+            capacity,
+            charge: Cell::new(capacity),
+            current: Cell::new(0.0),
+            voltage: Cell::new(0.0),
+            saved: None,
+        };
+        battery.voltage.set(battery.open_circuit_voltage());
+        battery
+    }
+
+    pub fn soc(&self) -> f32 {
+        (self.charge.get() / self.capacity) as f32
+    }
+
+    // 充電率に対する開放電圧
+    pub fn open_circuit_voltage(&self) -> f32 {
+        let soc = self.soc();
+        match self.ocv.iter().position(|(s, _)| soc < *s) {
+            None => self.ocv[self.ocv.len() - 1].1,
+            Some(0) => self.ocv[0].1,
+            Some(i) => {
+                let (s0, v0) = self.ocv[i - 1];
+                let (s1, v1) = self.ocv[i];
+                v0 + (v1 - v0) * (soc - s0) / (s1 - s0)
+            }
+        }
+    }
+
+    // 充電率 (0.0 - 1.0) を変更する
+    pub fn set_soc(&mut self, soc: f32) {
+        let soc = soc.clamp(0.0, 1.0) as f64;
+        self.charge.set(self.capacity * soc);
+        self.voltage.set(self.open_circuit_voltage());
+    }
+}
+
+fn mah_to_coulomb(mah: f64) -> f64 {
+    mah * 3.6
+}
+
+impl Element for Battery {
+    fn kind(&self) -> &'static str {
+        "battery"
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        vec!["+", "-"]
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.to_vec()
+    }
+
+    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
+        self.pins[pin_id] = node_id;
+    }
+
+    fn output_pins(&self) -> Vec<bool> {
+        self.outputs.to_vec()
+    }
+
+    fn stamp(&self, eq: &mut Equation) {
+        // 出力ピンは 0 だけ. 電流は + 端子から電池に流れ込む向きが正.
+        //   V+ - V- - r * I = Voc
+        let index = eq.src(self.id, 0);
+        let p0 = eq.node(self.pins[0]);
+        let p1 = eq.node(self.pins[1]);
+        eq.add_a(p0, index, 1.0);
+        eq.add_a(p1, index, -1.0);
+        eq.add_a(index, p0, 1.0);
+        eq.add_a(index, p1, -1.0);
+        eq.add_a(index, index, -self.r);
+        eq.add_z(index, self.voltage.get());
+    }
+
+    fn solved(&self, eq: &Equation) {
+        self.current.set(-eq.src_current(self.id, 0));
+    }
+
+    // 放電した分だけ残量を減らし、開放電圧が変わった場合は true を返す.
+    fn clk(&self) -> bool {
+        let charge = self.charge.get() - self.current.get() as f64 / CLOCK_FREQUENCY;
+        self.charge.set(charge.max(0.0).min(self.capacity));
+
+        let voltage = self.open_circuit_voltage();
+        if (voltage - self.voltage.get()).abs() >= VOLTAGE_RESOLUTION {
+            self.voltage.set(voltage);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn save(&mut self) -> Result<(), String> {
+        self.saved = Some((self.charge.get(), self.current.get(), self.voltage.get()));
+        Ok(())
+    }
+
+    fn rewind(&mut self, _cycle: u64) {
+        if let Some((charge, current, voltage)) = self.saved {
+            self.charge.set(charge);
+            self.current.set(current);
+            self.voltage.set(voltage);
+        }
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        let i = eq.src_current(self.id, 0);
+        vec![i, -i]
+    }
+
+    fn outputs(&self, _eq: &Equation) -> ElementState {
+        let mut outputs = ElementState::new();
+        outputs.insert("soc", self.soc());
+        // 残量 [mAh]
+        outputs.insert("charge", (self.charge.get() / 3.6) as f32);
+        outputs.insert("open_circuit_voltage", self.voltage.get());
+        outputs
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            param("capacity", "mAh", 1e-9, INF),
+            param("r", "Ω", 0.0, INF),
+            param("soc", "", 0.0, 1.0),
+        ]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        match name {
+            "capacity" => Some((self.capacity / 3.6) as f32),
+            "r" => Some(self.r),
+            "soc" => Some(self.soc()),
+            _ => None,
+        }
+    }
+
+    // 容量を変更しても充電率は変わらない
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "capacity" => {
+                let soc = self.soc();
+                self.capacity = mah_to_coulomb(value as f64);
+                self.set_soc(soc);
+            }
+            "r" => self.r = value,
+            "soc" => self.set_soc(value),
+            _ => return Err(format!("unknown parameter: {}", name)),
+        }
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Simulator {
+    pub fn add_battery(&mut self, chemistry: BatteryChemistry) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let element = Rc::new(RefCell::new(Battery::new(id, chemistry)));
+        self.elements.insert(id, element);
+        id
+    }
+
+    // 容量 [mAh] と内部抵抗 [Ω] を変更し、満充電にする.
+    //   ・範囲外の値はエラーで、どちらも変更しない.
+    pub fn battery_set_params(
+        &mut self,
+        element_id: usize,
+        capacity: f64,
+        r: f32,
+    ) -> Result<(), String> {
+        let original = self.get_param(element_id, "capacity")?;
+        self.set_param(element_id, "capacity", capacity as f32)?;
+        if let Err(err) = self.set_param(element_id, "r", r) {
+            self.set_param(element_id, "capacity", original)?;
+            return Err(err);
+        }
+        self.set_param(element_id, "soc", 1.0)
+    }
+
+    // 充電率 (0.0 - 1.0) を変更する
+    pub fn battery_set_soc(&mut self, element_id: usize, soc: f32) -> Result<(), String> {
+        self.set_param(element_id, "soc", soc)
+    }
+}
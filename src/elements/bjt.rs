@@ -0,0 +1,354 @@
+use super::super::simulator::*;
+use super::element::*;
+use std::any::Any;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 接合に並列に入れる微小コンダクタンス. 遮断状態でも方程式が解けるようにする.
+const GMIN: f32 = 1e-12;
+
+// 動作点の領域を判定する接合電圧と飽和電圧 [V]
+const JUNCTION_ON: f32 = 0.5;
+const VCE_SAT: f32 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Polarity {
+    Npn,
+    Pnp,
+}
+
+// Gummel-Poon モデルのパラメータ
+//   ・VAF = ∞, RB = RC = RE = 0 の場合は Ebers-Moll モデルと同じになる.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BjtModel {
+    // 飽和電流 [A]
+    pub is: f32,
+    // 順方向電流増幅率
+    pub bf: f32,
+    // 逆方向電流増幅率
+    pub br: f32,
+    // 順方向アーリー電圧 [V]
+    pub vaf: f32,
+    // ベース / コレクタ / エミッタの直列抵抗 [Ω]
+    pub rb: f32,
+    pub rc: f32,
+    pub re: f32,
+}
+
+impl Default for BjtModel {
+    fn default() -> BjtModel {
+        BjtModel {
+            is: 1e-14,
+            bf: 100.0,
+            br: 1.0,
+            vaf: f32::INFINITY,
+            rb: 0.0,
+            rc: 0.0,
+            re: 0.0,
+        }
+    }
+}
+
+// バイポーラトランジスタ
+//   pins[0]: Base,  pins[1]: Collector,  pins[2]: Emitter
+//   ・直列抵抗がある場合は内部ノード B', C', E' を持つ. 直列抵抗が 0 のものは持たない.
+#[derive(Debug)]
+pub struct Bjt {
+    id: usize,
+    polarity: Polarity,
+    pins: [usize; 3],
+    model: BjtModel,
+    // 前回の stamp での接合電圧 (Vbe, Vbc)
+    junction: Cell<(f32, f32)>,
+    limited: Cell<bool>,
+}
+
+// 接合電圧 (Vbe, Vbc) における電流とその微分
+struct Linearized {
+    ic: f32,
+    ib: f32,
+    // (dIc/dVbe, dIc/dVbc, dIb/dVbe, dIb/dVbc)
+    g: (f32, f32, f32, f32),
+}
+
+impl Bjt {
+    pub fn new(id: usize, polarity: Polarity, model: BjtModel) -> Bjt {
+        Bjt {
+            id,
+            polarity,
+            pins: [0, 0, 0],
+            model,
+            junction: Cell::new((0.0, 0.0)),
+            limited: Cell::new(false),
+        }
+    }
+
+    pub fn set_model(&mut self, model: BjtModel) {
+        self.model = model;
+    }
+
+    fn sign(&self) -> f32 {
+        match self.polarity {
+            Polarity::Npn => 1.0,
+            Polarity::Pnp => -1.0,
+        }
+    }
+
+    fn resistances(&self) -> [f32; 3] {
+        [self.model.rb, self.model.rc, self.model.re]
+    }
+
+    // B, C, E の直列抵抗の内側の内部ノードが方程式の何段目に当たるか. 直列抵抗が 0 の場合は None.
+    fn internals(&self, eq: &Equation) -> [Option<usize>; 3] {
+        let mut internals = [None; 3];
+        let mut k = 0;
+        for (internal, r) in internals.iter_mut().zip(self.resistances().iter()) {
+            if *r > 0.0 {
+                *internal = eq.internal(self.id, k);
+                k += 1;
+            }
+        }
+        internals
+    }
+
+    // B, C, E 端子（直列抵抗がある場合は内部ノード）が方程式の何段目に当たるか
+    fn terminals(&self, eq: &Equation) -> [Option<usize>; 3] {
+        let internals = self.internals(eq);
+        let mut terminals = [None; 3];
+        for (i, terminal) in terminals.iter_mut().enumerate() {
+            *terminal = if self.resistances()[i] > 0.0 {
+                internals[i]
+            } else {
+                eq.node(self.pins[i])
+            };
+        }
+        terminals
+    }
+
+    fn linearize(&self, vbe: f32, vbc: f32) -> Linearized {
+        let m = &self.model;
+        let ef = (vbe / VT).exp();
+        let er = (vbc / VT).exp();
+        let i_f = m.is * (ef - 1.0) + GMIN * vbe;
+        let i_r = m.is * (er - 1.0) + GMIN * vbc;
+        let gf = m.is * ef / VT + GMIN;
+        let gr = m.is * er / VT + GMIN;
+
+        // アーリー効果: Ict = (If - Ir) * (1 - Vbc / VAF)
+        let k = 1.0 - vbc / m.vaf;
+        let ict = (i_f - i_r) * k;
+
+        Linearized {
+            ic: ict - i_r / m.br,
+            ib: i_f / m.bf + i_r / m.br,
+            g: (
+                gf * k,
+                -gr * k - (i_f - i_r) / m.vaf - gr / m.br,
+                gf / m.bf,
+                gr / m.br,
+            ),
+        }
+    }
+}
+
+impl Element for Bjt {
+    fn kind(&self) -> &'static str {
+        match self.polarity {
+            Polarity::Npn => "npn",
+            Polarity::Pnp => "pnp",
+        }
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        vec!["base", "collector", "emitter"]
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.to_vec()
+    }
+
+    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
+        self.pins[pin_id] = node_id;
+    }
+
+    fn internal_nodes(&self) -> usize {
+        self.resistances().iter().filter(|r| **r > 0.0).count()
+    }
+
+    fn stamp(&self, eq: &mut Equation) {
+        let [b, c, e] = self.terminals(eq);
+
+        // 直列抵抗
+        let internals = self.internals(eq);
+        for ((pin, internal), r) in self
+            .pins
+            .iter()
+            .zip(internals.iter())
+            .zip(self.resistances().iter())
+        {
+            if *r > 0.0 {
+                let outer = eq.node(*pin);
+                eq.stamp_conductance_index(outer, *internal, 1.0 / r);
+            }
+        }
+
+        // 接合電圧を求め、前回の値から急に変化しないよう制限する
+        let p = self.sign();
+        let vbe = p * (eq.value(b) - eq.value(e));
+        let vbc = p * (eq.value(b) - eq.value(c));
+        let (vbe_old, vbc_old) = self.junction.get();
+        let vcrit = vcrit(self.model.is, VT);
+        let (vbe, limited_be) = pnjlim(vbe, vbe_old, VT, vcrit);
+        let (vbc, limited_bc) = pnjlim(vbc, vbc_old, VT, vcrit);
+        self.junction.set((vbe, vbc));
+        self.limited.set(limited_be || limited_bc);
+
+        // Ic, Ib を (Vbe, Vbc) の周りで線形化する
+        //   I = g_be * Vbe + g_bc * Vbc + I_eq
+        let lin = self.linearize(vbe, vbc);
+        let (gcbe, gcbc, gbbe, gbbc) = lin.g;
+        let ic_eq = p * (lin.ic - gcbe * vbe - gcbc * vbc);
+        let ib_eq = p * (lin.ib - gbbe * vbe - gbbc * vbc);
+
+        eq.add_a(c, b, gcbe + gcbc);
+        eq.add_a(c, e, -gcbe);
+        eq.add_a(c, c, -gcbc);
+        eq.add_z(c, -ic_eq);
+
+        eq.add_a(b, b, gbbe + gbbc);
+        eq.add_a(b, e, -gbbe);
+        eq.add_a(b, c, -gbbc);
+        eq.add_z(b, -ib_eq);
+
+        // Ie = -(Ic + Ib)
+        eq.add_a(e, b, -(gcbe + gcbc + gbbe + gbbc));
+        eq.add_a(e, e, gcbe + gbbe);
+        eq.add_a(e, c, gcbc + gbbc);
+        eq.add_z(e, ic_eq + ib_eq);
+    }
+
+    fn limited(&self) -> bool {
+        self.limited.get()
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        let [b, c, e] = self.terminals(eq);
+        let p = self.sign();
+        let vbe = p * (eq.value(b) - eq.value(e));
+        let vbc = p * (eq.value(b) - eq.value(c));
+        let lin = self.linearize(vbe, vbc);
+        vec![p * lin.ib, p * lin.ic, -p * (lin.ib + lin.ic)]
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            param("is", "A", 1e-30, 1.0),
+            param("bf", "", 1e-3, INF),
+            param("br", "", 1e-3, INF),
+            param("vaf", "V", 1e-3, INF),
+            param("rb", "Ω", 0.0, INF),
+            param("rc", "Ω", 0.0, INF),
+            param("re", "Ω", 0.0, INF),
+        ]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        let m = &self.model;
+        match name {
+            "is" => Some(m.is),
+            "bf" => Some(m.bf),
+            "br" => Some(m.br),
+            "vaf" => Some(m.vaf),
+            "rb" => Some(m.rb),
+            "rc" => Some(m.rc),
+            "re" => Some(m.re),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        let mut m = self.model.clone();
+        match name {
+            "is" => m.is = value,
+            "bf" => m.bf = value,
+            "br" => m.br = value,
+            "vaf" => m.vaf = value,
+            "rb" => m.rb = value,
+            "rc" => m.rc = value,
+            "re" => m.re = value,
+            _ => return Err(format!("unknown parameter: {}", name)),
+        }
+        self.set_model(m);
+        Ok(())
+    }
+
+    // 領域は B-E 接合が順バイアスか (Vbe > JUNCTION_ON) と Vce で決める.
+    fn operating_point(&self, eq: &Equation) -> Option<(&'static str, ElementState)> {
+        let t = self.terminals(eq);
+        let v = |i: usize| eq.value(t[i]);
+        let vbe = self.sign() * (v(0) - v(2));
+        let vbc = self.sign() * (v(0) - v(1));
+        let region = if vbe > JUNCTION_ON {
+            if vbe - vbc < VCE_SAT {
+                "saturation"
+            } else {
+                "active"
+            }
+        } else if vbc > JUNCTION_ON {
+            "reverse"
+        } else {
+            "cutoff"
+        };
+
+        let l = self.linearize(vbe, vbc);
+        let mut params = ElementState::new();
+        params.insert("ic", l.ic);
+        params.insert("ib", l.ib);
+        params.insert("vbe", vbe);
+        params.insert("vce", vbe - vbc);
+        // gm = dIc/dVbe, gpi = dIb/dVbe, go = dIc/dVce
+        params.insert("gm", l.g.0);
+        params.insert("rpi", 1.0 / l.g.2);
+        params.insert("ro", 1.0 / -l.g.1);
+        Some((region, params))
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Simulator {
+    fn add_bjt(&mut self, polarity: Polarity) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let element = Rc::new(RefCell::new(Bjt::new(id, polarity, BjtModel::default())));
+        self.elements.insert(id, element);
+        id
+    }
+
+    pub fn add_npn(&mut self) -> usize {
+        self.add_bjt(Polarity::Npn)
+    }
+
+    pub fn add_pnp(&mut self) -> usize {
+        self.add_bjt(Polarity::Pnp)
+    }
+
+    // Gummel-Poon モデルのパラメータを設定する
+    //   ・直列抵抗の有無で方程式の次元が変わるので、状態は再計算が必要になる.
+    pub fn bjt_set_model(&mut self, element_id: usize, model: BjtModel) {
+        match self
+            .elements
+            .get(&element_id)
+            .unwrap()
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<Bjt>()
+        {
+            Some(bjt) => bjt.set_model(model),
+            None => panic!("is not Bjt"),
+        }
+        self.state = None;
+    }
+}
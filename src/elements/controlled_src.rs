@@ -0,0 +1,226 @@
+use super::super::simulator::*;
+use super::element::*;
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 制御電源の種類
+//   ・電流制御のものは、制御する電流として他の回路素子の src のピン（電圧源など）の電流を参照する.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControlledSrcKind {
+    // 電圧制御電圧源 (E): V = gain * Vc
+    Vcvs,
+    // 電圧制御電流源 (G): I = gain * Vc
+    Vccs,
+    // 電流制御電圧源 (H): V = gain * Ic
+    Ccvs { ctrl: (ElementId, PinId) },
+    // 電流制御電流源 (F): I = gain * Ic
+    Cccs { ctrl: (ElementId, PinId) },
+}
+
+// 制御電源
+//   pins[0]: 出力 +,  pins[1]: 出力 -,  pins[2]: 制御 +,  pins[3]: 制御 -
+//   ・電流源の電流は pins[0] から電源の中を通って pins[1] へ流れる向きを正とする.
+//   ・電流制御のものは制御端子を持たない.
+#[derive(Debug)]
+pub struct ControlledSrc {
+    id: usize,
+    kind: ControlledSrcKind,
+    pins: Vec<usize>,
+    gain: f32,
+}
+
+impl ControlledSrc {
+    pub fn new(id: usize, kind: ControlledSrcKind, gain: f32) -> ControlledSrc {
+        let pin_count = match kind {
+            ControlledSrcKind::Vcvs | ControlledSrcKind::Vccs => 4,
+            _ => 2,
+        };
+        ControlledSrc {
+            id,
+            kind,
+            pins: vec![0; pin_count],
+            gain,
+        }
+    }
+
+    pub fn change_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+}
+
+impl Element for ControlledSrc {
+    fn kind(&self) -> &'static str {
+        match self.kind {
+            ControlledSrcKind::Vcvs => "vcvs",
+            ControlledSrcKind::Vccs => "vccs",
+            ControlledSrcKind::Ccvs { .. } => "ccvs",
+            ControlledSrcKind::Cccs { .. } => "cccs",
+        }
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        match self.kind {
+            ControlledSrcKind::Vcvs | ControlledSrcKind::Vccs => {
+                vec!["out+", "out-", "ctrl+", "ctrl-"]
+            }
+            _ => vec!["out+", "out-"],
+        }
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.clone()
+    }
+
+    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
+        self.pins[pin_id] = node_id;
+    }
+
+    fn output_pins(&self) -> Vec<bool> {
+        match self.kind {
+            ControlledSrcKind::Vcvs => vec![true, false, false, false],
+            ControlledSrcKind::Ccvs { .. } => vec![true, false],
+            _ => vec![],
+        }
+    }
+
+    fn stamp(&self, eq: &mut Equation) {
+        let nodes: Vec<Option<usize>> = self.pins.iter().map(|node_id| eq.node(*node_id)).collect();
+        let src = eq.src(self.id, 0);
+        let ctrl = match self.kind {
+            //   ・制御する電流が見つからない場合は None となり、Ic = 0 とみなす.
+            //     CCVS の拘束式の行は残るので方程式は解ける.
+            ControlledSrcKind::Ccvs { ctrl } | ControlledSrcKind::Cccs { ctrl } => {
+                eq.src(ctrl.0, ctrl.1)
+            }
+            _ => None,
+        };
+
+        match self.kind {
+            ControlledSrcKind::Vcvs => {
+                // V(+) - V(-) - gain * (Vc(+) - Vc(-)) = 0
+                eq.add_a(nodes[0], src, 1.0);
+                eq.add_a(nodes[1], src, -1.0);
+                eq.add_a(src, nodes[0], 1.0);
+                eq.add_a(src, nodes[1], -1.0);
+                eq.add_a(src, nodes[2], -self.gain);
+                eq.add_a(src, nodes[3], self.gain);
+            }
+            ControlledSrcKind::Vccs => {
+                eq.add_a(nodes[0], nodes[2], self.gain);
+                eq.add_a(nodes[0], nodes[3], -self.gain);
+                eq.add_a(nodes[1], nodes[2], -self.gain);
+                eq.add_a(nodes[1], nodes[3], self.gain);
+            }
+            ControlledSrcKind::Ccvs { .. } => {
+                // V(+) - V(-) - gain * Ic = 0
+                eq.add_a(nodes[0], src, 1.0);
+                eq.add_a(nodes[1], src, -1.0);
+                eq.add_a(src, nodes[0], 1.0);
+                eq.add_a(src, nodes[1], -1.0);
+                eq.add_a(src, ctrl, -self.gain);
+            }
+            ControlledSrcKind::Cccs { .. } => {
+                eq.add_a(nodes[0], ctrl, self.gain);
+                eq.add_a(nodes[1], ctrl, -self.gain);
+            }
+        }
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        let i = match self.kind {
+            ControlledSrcKind::Vcvs | ControlledSrcKind::Ccvs { .. } => eq.src_current(self.id, 0),
+            ControlledSrcKind::Vccs => {
+                self.gain * (eq.voltage(self.pins[2]) - eq.voltage(self.pins[3]))
+            }
+            ControlledSrcKind::Cccs { ctrl } => self.gain * eq.src_current(ctrl.0, ctrl.1),
+        };
+        let mut currents = vec![0.0; self.pins.len()];
+        currents[0] = i;
+        currents[1] = -i;
+        currents
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![param("gain", "", -INF, INF)]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        match name {
+            "gain" => Some(self.gain),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "gain" => self.gain = value,
+            _ => return Err(format!("unknown parameter: {}", name)),
+        }
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Simulator {
+    fn add_controlled_src(&mut self, kind: ControlledSrcKind, gain: f32) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let element = Rc::new(RefCell::new(ControlledSrc::new(id, kind, gain)));
+        self.elements.insert(id, element);
+        id
+    }
+
+    // 電圧制御電圧源
+    pub fn add_vcvs(&mut self, gain: f32) -> usize {
+        self.add_controlled_src(ControlledSrcKind::Vcvs, gain)
+    }
+
+    // 電圧制御電流源
+    pub fn add_vccs(&mut self, gain: f32) -> usize {
+        self.add_controlled_src(ControlledSrcKind::Vccs, gain)
+    }
+
+    // 電流制御電圧源. ctrl_element_id の回路素子（電圧源など）に流れる電流で制御する.
+    pub fn add_ccvs(&mut self, gain: f32, ctrl_element_id: ElementId) -> Result<usize, String> {
+        let ctrl = self.current_ctrl(ctrl_element_id)?;
+        Ok(self.add_controlled_src(ControlledSrcKind::Ccvs { ctrl }, gain))
+    }
+
+    // 電流制御電流源. ctrl_element_id の回路素子（電圧源など）に流れる電流で制御する.
+    pub fn add_cccs(&mut self, gain: f32, ctrl_element_id: ElementId) -> Result<usize, String> {
+        let ctrl = self.current_ctrl(ctrl_element_id)?;
+        Ok(self.add_controlled_src(ControlledSrcKind::Cccs { ctrl }, gain))
+    }
+
+    // 制御する電流. 電流を方程式の変数に持つ（src のピンがある）回路素子でなければならない.
+    fn current_ctrl(&self, ctrl_element_id: ElementId) -> Result<(ElementId, PinId), String> {
+        let element = self
+            .elements
+            .get(&ctrl_element_id)
+            .ok_or(format!("unknown element: {}", ctrl_element_id))?;
+        if element.borrow().output_pins().first() != Some(&true) {
+            return Err(format!(
+                "element {} has no branch current to control",
+                ctrl_element_id
+            ));
+        }
+        Ok((ctrl_element_id, 0))
+    }
+
+    pub fn controlled_src_change_gain(&mut self, element_id: usize, gain: f32) {
+        match self
+            .elements
+            .get(&element_id)
+            .unwrap()
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<ControlledSrc>()
+        {
+            Some(src) => src.change_gain(gain),
+            None => panic!("is not ControlledSrc"),
+        }
+    }
+}
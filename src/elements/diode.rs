@@ -9,6 +9,9 @@ use wasm_bindgen::prelude::*;
 // 順方向電圧 Vd における電流 I(Vd) を区分線形近似でモデリングする.
 // I(V) = 0                      ( Vd <= threshold )
 //      = grad * (V - threshold) ( Vd  > threshold )
+// 降伏電圧 bv を設定した場合（ツェナーダイオード）は、逆方向にも区分線形で電流が流れる.
+// 逆方向電流が ibv となる電圧を -bv とし、それ以上の逆電圧では動作抵抗 rz で増える.
+// I(V) = -ibv - (-V - bv) / rz  ( Vd < -(bv - ibv * rz) )
 #[derive(Debug)]
 pub struct Diode {
     id: usize,
@@ -16,71 +19,214 @@ pub struct Diode {
     pins: [usize; 2],
     threshold: f32,
     grad: f32,
+    breakdown: Option<Breakdown>,
+}
+
+// 逆方向の降伏特性
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Breakdown {
+    // 降伏電圧 [V]
+    pub bv: f32,
+    // 降伏電圧における逆方向電流（ニー電流） [A]
+    pub ibv: f32,
+    // 降伏領域の動作抵抗 [Ω]
+    pub rz: f32,
+}
+
+impl Breakdown {
+    pub fn new(bv: f32) -> Breakdown {
+        Breakdown {
+            bv,
+            ibv: 0.001,
+            rz: 5.0,
+        }
+    }
+
+    // 逆方向に電流が流れ始める電圧
+    fn knee(&self) -> f32 {
+        self.bv - self.ibv * self.rz
+    }
+}
+
+// SPICE のダイオードモデルのパラメータ
+//   ・区分線形モデルには、I = 1mA と 10mA の 2 点を通る直線として変換する.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiodeModel {
+    // 飽和電流 [A]
+    pub is: f32,
+    // 放出係数
+    pub n: f32,
+    // 直列抵抗 [Ω]
+    pub rs: f32,
+    // 降伏電圧 [V] (0 の場合は降伏しない) と、その時の逆方向電流 [A]
+    pub bv: f32,
+    pub ibv: f32,
+}
+
+impl Default for DiodeModel {
+    // 1N4148 程度の値
+    fn default() -> DiodeModel {
+        DiodeModel {
+            is: 2.52e-9,
+            n: 1.752,
+            rs: 0.568,
+            bv: 0.0,
+            ibv: 1e-4,
+        }
+    }
+}
+
+impl DiodeModel {
+    // 区分線形モデルの (threshold, grad)
+    pub fn pwl(&self) -> (f32, f32) {
+        let volt = |i: f32| self.n * VT * (i / self.is).ln() + i * self.rs;
+        let (i1, i2) = (0.001, 0.01);
+        let grad = (i2 - i1) / (volt(i2) - volt(i1));
+        (volt(i1) - i1 / grad, grad)
+    }
+
+    pub fn breakdown(&self) -> Option<Breakdown> {
+        if self.bv > 0.0 {
+            Some(Breakdown {
+                bv: self.bv,
+                ibv: self.ibv,
+                rz: if self.rs > 0.0 { self.rs } else { 5.0 },
+            })
+        } else {
+            None
+        }
+    }
 }
 
 impl Diode {
     pub fn new(id: usize) -> Diode {
         Diode {
-            id: id,
+            id,
             pins: [0, 0],
             threshold: 0.674,
             grad: 0.191,
+            breakdown: None,
         }
     }
 
-    pub fn current(&self, volt: f32) -> f32 {
-        if volt <= self.threshold {
-            0.0
-        } else {
-            self.grad * (volt - self.threshold)
+    pub fn zener(id: usize, bv: f32) -> Diode {
+        Diode {
+            breakdown: Some(Breakdown::new(bv)),
+            ..Diode::new(id)
         }
     }
 
+    pub fn set_model(&mut self, model: DiodeModel) {
+        let (threshold, grad) = model.pwl();
+        self.threshold = threshold;
+        self.grad = grad;
+        self.breakdown = model.breakdown();
+    }
+
+    pub fn current(&self, volt: f32) -> f32 {
+        let (g, i_eq) = self.linearize(volt);
+        g * volt + i_eq
+    }
+
     pub fn d_current(&self, volt: f32) -> f32 {
-        if volt <= self.threshold {
-            0.0
-        } else {
-            self.grad
+        self.linearize(volt).0
+    }
+
+    // 電圧 volt を含む区間の直線 I = g * V + i_eq
+    fn linearize(&self, volt: f32) -> (f32, f32) {
+        match self.breakdown {
+            Some(b) if volt < -b.knee() => (1.0 / b.rz, b.knee() / b.rz),
+            _ if volt <= self.threshold => (0.0, 0.0),
+            _ => (self.grad, -self.grad * self.threshold),
         }
     }
 }
 
 impl Element for Diode {
+    fn kind(&self) -> &'static str {
+        match self.breakdown {
+            Some(_) => "zener",
+            None => "diode",
+        }
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        vec!["anode", "cathode"]
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.to_vec()
+    }
+
     fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
         self.pins[pin_id] = node_id;
     }
 
     // cf. https://spicesharp.github.io/SpiceSharp/articles/custom_components/modified_nodal_analysis.html
     fn stamp(&self, eq: &mut Equation) {
-        let g = self.threshold * self.grad;
-        match self.pins {
-            [p0, 0] => {
-                let p0 = *eq.node_index.get(&p0).unwrap();
-                eq.z[p0] += g;
-
-                let didv = self.d_current(eq.x[p0]);
-                eq.a[(p0, p0)] += didv;
-            }
-            [0, p1] => {
-                let p1 = *eq.node_index.get(&p1).unwrap();
-                eq.z[p1] -= g;
+        let anode = eq.node(self.pins[0]);
+        let cathode = eq.node(self.pins[1]);
+        let (g, i_eq) = self.linearize(eq.value(anode) - eq.value(cathode));
+        eq.stamp_conductance_index(anode, cathode, g);
+        eq.add_z(anode, -i_eq);
+        eq.add_z(cathode, i_eq);
+    }
 
-                let didv = self.d_current(eq.x[p1]);
-                eq.a[(p1, p1)] += didv;
-            }
-            [p0, p1] => {
-                let p0 = *eq.node_index.get(&p0).unwrap();
-                let p1 = *eq.node_index.get(&p1).unwrap();
-                eq.z[p0] += g;
-                eq.z[p1] -= g;
-
-                let didv = self.d_current(eq.x[p0] - eq.x[p1]);
-                eq.a[(p0, p1)] -= didv;
-                eq.a[(p1, p0)] -= didv;
-                eq.a[(p0, p0)] += didv;
-                eq.a[(p1, p1)] += didv;
-            }
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        let i = self.current(eq.voltage(self.pins[0]) - eq.voltage(self.pins[1]));
+        vec![i, -i]
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        let mut params = vec![
+            param("threshold", "V", 0.0, INF),
+            param("grad", "S", 1e-6, INF),
+            // 0 の場合は降伏しない
+            param("bv", "V", 0.0, INF),
+        ];
+        if self.breakdown.is_some() {
+            params.push(param("ibv", "A", 0.0, INF));
+            params.push(param("rz", "Ω", 0.01, INF));
+        }
+        params
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        match (name, self.breakdown) {
+            ("threshold", _) => Some(self.threshold),
+            ("grad", _) => Some(self.grad),
+            ("bv", b) => Some(b.map_or(0.0, |b| b.bv)),
+            ("ibv", Some(b)) => Some(b.ibv),
+            ("rz", Some(b)) => Some(b.rz),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match (name, self.breakdown.as_mut()) {
+            ("threshold", _) => self.threshold = value,
+            ("grad", _) => self.grad = value,
+            ("bv", _) if value == 0.0 => self.breakdown = None,
+            ("bv", Some(b)) => b.bv = value,
+            ("bv", None) => self.breakdown = Some(Breakdown::new(value)),
+            ("ibv", Some(b)) => b.ibv = value,
+            ("rz", Some(b)) => b.rz = value,
+            _ => return Err(format!("unknown parameter: {}", name)),
         }
+        Ok(())
+    }
+
+    fn operating_point(&self, eq: &Equation) -> Option<(&'static str, ElementState)> {
+        let volt = eq.voltage(self.pins[0]) - eq.voltage(self.pins[1]);
+        let region = match self.breakdown {
+            Some(b) if volt < -b.knee() => "breakdown",
+            _ if volt > self.threshold => "on",
+            _ => "off",
+        };
+        let mut params = ElementState::new();
+        params.insert("id", self.current(volt));
+        params.insert("gd", self.d_current(volt));
+        Some((region, params))
     }
 
     fn as_any(&mut self) -> &mut dyn Any {
@@ -95,4 +241,48 @@ impl Simulator {
         self.elements.insert(id, element);
         id
     }
+
+    // 降伏電圧 bv のツェナーダイオードを作成する
+    pub fn add_zener(&mut self, bv: f32) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let element = Rc::new(RefCell::new(Diode::zener(id, bv)));
+        self.elements.insert(id, element);
+        id
+    }
+
+    // 降伏特性を設定する. bv <= 0 の場合は降伏しない通常のダイオードになる.
+    pub fn diode_set_breakdown(&mut self, element_id: usize, bv: f32, ibv: f32, rz: f32) {
+        match self
+            .elements
+            .get(&element_id)
+            .unwrap()
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<Diode>()
+        {
+            Some(diode) => {
+                diode.breakdown = if bv > 0.0 {
+                    Some(Breakdown { bv, ibv, rz })
+                } else {
+                    None
+                }
+            }
+            None => panic!("is not Diode"),
+        }
+    }
+
+    pub fn diode_set_model(&mut self, element_id: usize, model: DiodeModel) {
+        match self
+            .elements
+            .get(&element_id)
+            .unwrap()
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<Diode>()
+        {
+            Some(diode) => diode.set_model(model),
+            None => panic!("is not Diode"),
+        }
+        self.state = None;
+    }
 }
@@ -1,14 +1,136 @@
-use super::super::simulator::Equation;
+use super::super::ac::AcEquation;
+use super::super::simulator::{ElementState, Equation, NodeId};
+use serde::*;
 use std::any::Any;
 
+// 名前で変更できる回路素子のパラメータ
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub unit: &'static str,
+    // 設定できる範囲
+    pub min: f32,
+    pub max: f32,
+    // 0 / 1 などの離散的な値だけを取る（スイッチの状態など）. 微小に変化させる解析では使わない.
+    pub discrete: bool,
+}
+
 pub trait Element {
     fn as_any(&mut self) -> &mut dyn Any;
+    // 回路素子の種類 ("registor", "npn" など)
+    fn kind(&self) -> &'static str;
+    // ピン名. 添字が pin_id になる.
+    fn pin_names(&self) -> Vec<&'static str>;
+    // 各ピンが結合しているノードの id. 結合していないピンは GND (0).
+    fn pin_nodes(&self) -> Vec<NodeId>;
     fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize);
     fn stamp(&self, eq: &mut Equation);
     fn clk(&self) -> bool {
         false
     }
+    // AC 解析の励振（独立電源）と、周波数によって変わる項を押す.
+    fn stamp_ac(&self, _eq: &Equation, _ac: &mut AcEquation) {}
+    // 時刻を戻せるように、現在の内部状態（電池の残量など）を保存する.
+    //   ・内部状態を戻せない素子は Err を返す.
+    fn save(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    // シミュレーション時刻を cycle (save した時刻) に戻し、内部状態を save した時点のものにする.
+    fn rewind(&mut self, _cycle: u64) {}
     fn output_pins(&self) -> Vec<bool> {
         vec![]
     }
+    // 回路に接続しない内部ノードの数 (BJT の直列抵抗の内側など). Equation::internal で参照する.
+    fn internal_nodes(&self) -> usize {
+        0
+    }
+    // 方程式の解から、各端子に流れ込む電流を求める
+    fn currents(&self, _eq: &Equation) -> Vec<f32> {
+        vec![]
+    }
+    // 方程式が解けた時に呼ばれる. 解を次のクロックで使う素子（電池の放電など）が保持する.
+    fn solved(&self, _eq: &Equation) {}
+    // 方程式の解から求まる、状態として出力する値（LED の明るさなど）
+    fn outputs(&self, _eq: &Equation) -> ElementState {
+        ElementState::new()
+    }
+    // from < t <= to の範囲で、出力が不連続に変わる時刻
+    fn breakpoints(&self, _from: f64, _to: f64) -> Vec<f64> {
+        vec![]
+    }
+    // 現在のクロックと次のクロックの間にあるブレークポイントの時刻 [s] で出力を評価する.
+    //   ・次の clk で通常の時刻に戻る.
+    fn seek(&self, _time: f64) {}
+    // 非線形素子の動作領域 ("on", "saturation" など) と小信号パラメータ
+    fn operating_point(&self, _eq: &Equation) -> Option<(&'static str, ElementState)> {
+        None
+    }
+    // 名前で変更できるパラメータの一覧
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![]
+    }
+    // パラメータの値. 一覧にない場合は None.
+    fn get_param(&self, _name: &str) -> Option<f32> {
+        None
+    }
+    // パラメータを変更する. 範囲は Simulator::set_param で確認している.
+    fn set_param(&mut self, name: &str, _value: f32) -> Result<(), String> {
+        Err(format!("unknown parameter: {}", name))
+    }
+    // MCU の各ピンの論理レベル (High: true)
+    fn pin_levels(&self) -> Vec<bool> {
+        vec![]
+    }
+    // 直前の stamp で接合電圧を制限した場合は true. 制限している間は収束とみなさない.
+    fn limited(&self) -> bool {
+        false
+    }
+}
+
+// 熱電圧 kT/q [V] (300K)
+pub const VT: f32 = 0.025852;
+
+// pn 接合の電圧制限 (SPICE の pnjlim)
+//   ・Newton 法で接合電圧が急に大きくなると exp が発散するので、前回の値からの変化を
+//     対数的に抑える. 制限した場合は true を返す.
+pub fn pnjlim(vnew: f32, vold: f32, vt: f32, vcrit: f32) -> (f32, bool) {
+    if vnew > vcrit && (vnew - vold).abs() > 2.0 * vt {
+        if vold > 0.0 {
+            let arg = 1.0 + (vnew - vold) / vt;
+            if arg > 0.0 {
+                (vold + vt * arg.ln(), true)
+            } else {
+                (vcrit, true)
+            }
+        } else {
+            (vt * (vnew / vt).ln(), true)
+        }
+    } else {
+        (vnew, false)
+    }
+}
+
+// pnjlim の制限を始める電圧
+pub fn vcrit(is: f32, vt: f32) -> f32 {
+    vt * (vt / (std::f32::consts::SQRT_2 * is)).ln()
+}
+
+pub const INF: f32 = f32::INFINITY;
+
+// パラメータの一覧を作る
+pub fn param(name: &'static str, unit: &'static str, min: f32, max: f32) -> ParamInfo {
+    ParamInfo {
+        name,
+        unit,
+        min,
+        max,
+        discrete: false,
+    }
+}
+
+pub fn discrete_param(name: &'static str, unit: &'static str, min: f32, max: f32) -> ParamInfo {
+    ParamInfo {
+        discrete: true,
+        ..param(name, unit, min, max)
+    }
 }
@@ -0,0 +1,112 @@
+use super::super::ac::AcEquation;
+use super::super::simulator::*;
+use super::element::*;
+use super::waveform::*;
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 独立電流源
+//   ・pins[0] から素子の中を通って pins[1] へ電流を流す (SPICE の I と同じ向き).
+#[derive(Debug)]
+pub struct IndCurrentSrc {
+    pins: [usize; 2],
+    pub source: Source,
+}
+
+impl IndCurrentSrc {
+    pub fn new(current: f32) -> IndCurrentSrc {
+        IndCurrentSrc {
+            pins: [0, 0],
+            source: Source::new(current),
+        }
+    }
+}
+
+impl Element for IndCurrentSrc {
+    fn kind(&self) -> &'static str {
+        "ind_current_src"
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        vec!["+", "-"]
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.to_vec()
+    }
+
+    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
+        self.pins[pin_id] = node_id;
+    }
+
+    fn stamp(&self, eq: &mut Equation) {
+        let i = self.source.stamp_value();
+        let p0 = eq.node(self.pins[0]);
+        let p1 = eq.node(self.pins[1]);
+        eq.add_z(p0, -i);
+        eq.add_z(p1, i);
+    }
+
+    fn clk(&self) -> bool {
+        self.source.clk()
+    }
+
+    fn stamp_ac(&self, eq: &Equation, ac: &mut AcEquation) {
+        let i = self.source.ac_phasor();
+        ac.add_z(eq.node(self.pins[0]), -i);
+        ac.add_z(eq.node(self.pins[1]), i);
+    }
+
+    fn rewind(&mut self, cycle: u64) {
+        self.source.set_cycle(cycle);
+    }
+
+    fn breakpoints(&self, from: f64, to: f64) -> Vec<f64> {
+        self.source.breakpoints(from, to)
+    }
+
+    fn seek(&self, time: f64) {
+        self.source.seek(time);
+    }
+
+    fn currents(&self, _eq: &Equation) -> Vec<f32> {
+        let i = self.source.value();
+        vec![i, -i]
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![param("current", "A", -INF, INF)]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        match name {
+            "current" => Some(self.source.level()),
+            _ => None,
+        }
+    }
+
+    // 波形を指定している場合は、波形の基準の値を変えて全体をずらす
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "current" => self.source.set_level(value),
+            _ => return Err(format!("unknown parameter: {}", name)),
+        }
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Simulator {
+    pub fn add_ind_current_src(&mut self, i: f32) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let mut src = IndCurrentSrc::new(i);
+        src.source.set_cycle(self.cycle);
+        let element = Rc::new(RefCell::new(src));
+        self.elements.insert(id, element);
+        id
+    }
+}
@@ -1,5 +1,7 @@
+use super::super::ac::AcEquation;
 use super::super::simulator::*;
 use super::element::*;
+use super::waveform::*;
 use std::any::Any;
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -10,21 +12,33 @@ pub struct IndVoltageSrc {
     id: usize,
     pins: [usize; 2],
     outputs: [bool; 2],
-    voltage: f32,
+    pub source: Source,
 }
 
 impl IndVoltageSrc {
     pub fn new(id: usize, volt: f32) -> IndVoltageSrc {
         IndVoltageSrc {
-            id: id,
+            id,
             pins: [0, 0],
             outputs: [true, false],
-            voltage: volt,
+            source: Source::new(volt),
         }
     }
 }
 
 impl Element for IndVoltageSrc {
+    fn kind(&self) -> &'static str {
+        "ind_voltage_src"
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        vec!["+", "-"]
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.to_vec()
+    }
+
     fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
         self.pins[pin_id] = node_id;
     }
@@ -36,7 +50,7 @@ impl Element for IndVoltageSrc {
     fn stamp(&self, eq: &mut Equation) {
         // 出力ピンは 0 だけ
         let index = eq.src_index.get(&(self.id, 0)).unwrap() + eq.node_index.len();
-        eq.z[index] = self.voltage;
+        eq.z[index] = self.source.stamp_value();
 
         match self.pins {
             [p0, 0] => {
@@ -60,6 +74,51 @@ impl Element for IndVoltageSrc {
         }
     }
 
+    fn clk(&self) -> bool {
+        self.source.clk()
+    }
+
+    fn stamp_ac(&self, eq: &Equation, ac: &mut AcEquation) {
+        ac.add_z(eq.src(self.id, 0), self.source.ac_phasor());
+    }
+
+    fn rewind(&mut self, cycle: u64) {
+        self.source.set_cycle(cycle);
+    }
+
+    fn breakpoints(&self, from: f64, to: f64) -> Vec<f64> {
+        self.source.breakpoints(from, to)
+    }
+
+    fn seek(&self, time: f64) {
+        self.source.seek(time);
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        let i = eq.src_current(self.id, 0);
+        vec![i, -i]
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![param("voltage", "V", -INF, INF)]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        match name {
+            "voltage" => Some(self.source.level()),
+            _ => None,
+        }
+    }
+
+    // 波形を指定している場合は、波形の基準の値を変えて全体をずらす
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "voltage" => self.source.set_level(value),
+            _ => return Err(format!("unknown parameter: {}", name)),
+        }
+        Ok(())
+    }
+
     fn as_any(&mut self) -> &mut dyn Any {
         self
     }
@@ -68,7 +127,9 @@ impl Element for IndVoltageSrc {
 impl Simulator {
     pub fn add_ind_voltage_src(&mut self, v: f32) -> usize {
         let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
-        let element = Rc::new(RefCell::new(IndVoltageSrc::new(id, v)));
+        let mut src = IndVoltageSrc::new(id, v);
+        src.source.set_cycle(self.cycle);
+        let element = Rc::new(RefCell::new(src));
         self.elements.insert(id, element);
         id
     }
@@ -0,0 +1,185 @@
+use super::super::simulator::*;
+use super::element::*;
+use serde::*;
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+// 順方向電圧以下でも方程式が解けるように入れる微小コンダクタンス
+const GMIN: f32 = 1e-12;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum LedColor {
+    Red,
+    Green,
+    Blue,
+    White,
+}
+
+// LED
+//   ・Diode と同じく区分線形近似でモデリングする. 順方向電圧 threshold を超えると
+//     直列抵抗 rs を通して電流が流れる.
+//   I(V) = 0                    ( V <= threshold )
+//        = (V - threshold) / rs ( V  > threshold )
+//   ・明るさ（相対光度）は電流にほぼ比例するので、定格電流で 1 となる値を出力する.
+#[derive(Debug)]
+pub struct Led {
+    // pins[0]: Anode,  pins[1]: Cathode
+    pins: [usize; 2],
+    threshold: f32,
+    rs: f32,
+    // 定格電流 [A]
+    i_max: f32,
+}
+
+impl Led {
+    pub fn new(color: LedColor) -> Led {
+        // 5mm 砲弾型 LED の 20mA 時の順方向電圧程度になる値. 色はこの初期値を決めるだけ.
+        let (threshold, rs) = match color {
+            LedColor::Red => (1.8, 10.0),
+            LedColor::Green => (2.8, 12.0),
+            LedColor::Blue => (2.9, 12.0),
+            LedColor::White => (2.9, 12.0),
+        };
+        Led {
+            pins: [0, 0],
+            threshold,
+            rs,
+            i_max: 0.02,
+        }
+    }
+
+    pub fn set_params(&mut self, threshold: f32, rs: f32, i_max: f32) {
+        self.threshold = threshold;
+        self.rs = rs;
+        self.i_max = i_max;
+    }
+
+    pub fn current(&self, volt: f32) -> f32 {
+        if volt <= self.threshold {
+            GMIN * volt
+        } else {
+            (volt - self.threshold) / self.rs
+        }
+    }
+
+    fn voltage(&self, eq: &Equation) -> f32 {
+        eq.voltage(self.pins[0]) - eq.voltage(self.pins[1])
+    }
+}
+
+impl Element for Led {
+    fn kind(&self) -> &'static str {
+        "led"
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        vec!["anode", "cathode"]
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.to_vec()
+    }
+
+    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
+        self.pins[pin_id] = node_id;
+    }
+
+    fn stamp(&self, eq: &mut Equation) {
+        // I = g * V + I_eq
+        let (g, i_eq) = if self.voltage(eq) <= self.threshold {
+            (GMIN, 0.0)
+        } else {
+            (1.0 / self.rs, -self.threshold / self.rs)
+        };
+        let anode = eq.node(self.pins[0]);
+        let cathode = eq.node(self.pins[1]);
+        eq.stamp_conductance_index(anode, cathode, g);
+        eq.add_z(anode, -i_eq);
+        eq.add_z(cathode, i_eq);
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        let i = self.current(self.voltage(eq));
+        vec![i, -i]
+    }
+
+    fn outputs(&self, eq: &Equation) -> ElementState {
+        let i = self.current(self.voltage(eq));
+        let mut outputs = ElementState::new();
+        outputs.insert("current", i);
+        outputs.insert("brightness", (i / self.i_max).max(0.0));
+        outputs.insert("over_current", if i > self.i_max { 1.0 } else { 0.0 });
+        outputs
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            param("threshold", "V", 0.0, INF),
+            param("rs", "Ω", 0.01, INF),
+            param("i_max", "A", 1e-6, INF),
+        ]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        match name {
+            "threshold" => Some(self.threshold),
+            "rs" => Some(self.rs),
+            "i_max" => Some(self.i_max),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "threshold" => self.threshold = value,
+            "rs" => self.rs = value,
+            "i_max" => self.i_max = value,
+            _ => return Err(format!("unknown parameter: {}", name)),
+        }
+        Ok(())
+    }
+
+    fn operating_point(&self, eq: &Equation) -> Option<(&'static str, ElementState)> {
+        let volt = self.voltage(eq);
+        let (region, gd) = if volt > self.threshold {
+            ("on", 1.0 / self.rs)
+        } else {
+            ("off", GMIN)
+        };
+        let mut params = ElementState::new();
+        params.insert("id", self.current(volt));
+        params.insert("gd", gd);
+        Some((region, params))
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Simulator {
+    pub fn add_led(&mut self, color: LedColor) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let element = Rc::new(RefCell::new(Led::new(color)));
+        self.elements.insert(id, element);
+        id
+    }
+
+    // 順方向電圧 [V], 直列抵抗 [Ω], 定格電流 [A] を変化させる
+    pub fn led_set_params(&mut self, element_id: usize, threshold: f32, rs: f32, i_max: f32) {
+        match self
+            .elements
+            .get(&element_id)
+            .unwrap()
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<Led>()
+        {
+            Some(led) => led.set_params(threshold, rs, i_max),
+            None => panic!("is not Led"),
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use super::super::elf::*;
+use super::super::simulator::*;
+use super::element::*;
+use avr_emulator::arch::atmega328p::*;
+use avr_emulator::avrmcu::AVRMCU;
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// MCU のコア
+//   ・avr_emulator のチップを共通のインターフェイスで扱うためのもの.
+//   ・新しいチップはこのトレイトを実装すればボードとして使えるようになる.
+//   ・avr_emulator にあるのは ATmega328P だけなので、Mega 2560 (ATmega2560) と
+//     ATtiny85 のボードはまだない.
+pub trait McuCore {
+    // プログラム (Intel HEX) を書き込み、初期化する
+    fn load_hex(&mut self, hex: String);
+    // 各ピンの出力レベル (High: true)
+    fn pin_states(&self) -> Vec<bool>;
+    // 1 クロック進める
+    fn step(&mut self);
+    // EEPROM を持つ（load_eeprom で初期値を書き込める）か
+    fn supports_eeprom(&self) -> bool {
+        false
+    }
+    // EEPROM に初期値を書き込む
+    fn load_eeprom(&mut self, _data: &[u8]) -> Result<(), String> {
+        Err("EEPROM is not supported by this MCU core".to_string())
+    }
+}
+
+impl McuCore for ATmega328P {
+    fn load_hex(&mut self, hex: String) {
+        self.program(hex);
+        self.initialize();
+    }
+
+    fn pin_states(&self) -> Vec<bool> {
+        self.get_pins().to_vec()
+    }
+
+    fn step(&mut self) {
+        self.next();
+    }
+}
+
+// ボードの定義
+//   ・ボードのピン番号 (pin_id) とコアのピン番号の対応表と、出力電圧からなる.
+#[derive(Debug)]
+pub struct Board {
+    pub name: &'static str,
+    // ピン名. 添字が pin_id になる.
+    pub pin_names: &'static [&'static str],
+    // 各ピンに対応するコアのピン番号. アナログ入力専用のピンは None.
+    pub core_pins: &'static [Option<usize>],
+    pub voltage: f32,
+}
+
+impl PartialEq for Board {
+    fn eq(&self, other: &Board) -> bool {
+        self.name == other.name
+    }
+}
+
+// MCU を載せたボード
+pub struct Mcu {
+    id: usize,
+    board: &'static Board,
+    pins: Vec<usize>,
+    outputs: Vec<bool>,
+    core: Rc<RefCell<dyn McuCore>>,
+    // ELF から読み込んだシンボル表. Intel HEX の場合は空.
+    symbols: Symbols,
+}
+
+impl Mcu {
+    pub fn new(id: usize, board: &'static Board, core: Rc<RefCell<dyn McuCore>>) -> Mcu {
+        Mcu {
+            id,
+            board,
+            pins: vec![0; board.pin_names.len()],
+            outputs: vec![false; board.pin_names.len()],
+            core,
+            symbols: Symbols::default(),
+        }
+    }
+
+    pub fn board(&self) -> &'static Board {
+        self.board
+    }
+
+    pub fn program(&mut self, hex: String) {
+        self.core.borrow_mut().load_hex(hex);
+        self.symbols = Symbols::default();
+    }
+
+    // avr-gcc / arduino-cli が出力した ELF を書き込む
+    //   ・Flash と EEPROM のセクションを読み込み、シンボル表を保持する.
+    //   ・書き込めないセクションがある場合は、何も書き込まずにエラーを返す.
+    pub fn program_elf(&mut self, elf: &[u8]) -> Result<(), String> {
+        let program = load_elf(elf)?;
+        if !program.eeprom.is_empty() && !self.core.borrow().supports_eeprom() {
+            return Err(format!("{} has no EEPROM to program", self.board.name));
+        }
+        self.core.borrow_mut().load_hex(program.hex);
+        self.symbols = program.symbols;
+        if !program.eeprom.is_empty() {
+            self.core.borrow_mut().load_eeprom(&program.eeprom)?;
+        }
+        Ok(())
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    // コアのピンの状態をボードのピン番号順に並べ替える
+    fn levels(&self, pin_state: &[bool]) -> Vec<bool> {
+        self.board
+            .core_pins
+            .iter()
+            .map(|core_pin| match core_pin {
+                Some(index) => pin_state.get(*index).cloned().unwrap_or(false),
+                None => false,
+            })
+            .collect()
+    }
+}
+
+impl Element for Mcu {
+    fn kind(&self) -> &'static str {
+        self.board.name
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        self.board.pin_names.to_vec()
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.clone()
+    }
+
+    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
+        self.pins[pin_id] = node_id;
+        // ノードに接続したピンは、一旦全て出力ピンとする（アナログ入力専用のピンを除く）
+        self.outputs[pin_id] = self.board.core_pins[pin_id].is_some();
+    }
+
+    fn output_pins(&self) -> Vec<bool> {
+        self.outputs.clone()
+    }
+
+    fn stamp(&self, eq: &mut Equation) {
+        let levels = self.levels(&self.core.borrow().pin_states());
+        for (pin_id, is_output) in self.outputs.iter().enumerate() {
+            if *is_output && levels[pin_id] {
+                let src_index = eq.src_index.get(&(self.id, pin_id)).unwrap() + eq.node_index.len();
+
+                eq.z[src_index] = self.board.voltage;
+
+                let node_index = *eq.node_index.get(&self.pins[pin_id]).unwrap();
+                eq.a[(node_index, src_index)] = 1.0;
+                eq.a[(src_index, node_index)] = 1.0;
+            }
+        }
+    }
+
+    fn clk(&self) -> bool {
+        let mut core = self.core.borrow_mut();
+        let pins = core.pin_states();
+        core.step();
+        pins != core.pin_states()
+    }
+
+    // コアの内部状態（レジスタやメモリ）は保存できない
+    fn save(&mut self) -> Result<(), String> {
+        Err(format!("{} cannot be rewound", self.board.name))
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        (0..self.pins.len())
+            .map(|pin_id| eq.src_current(self.id, pin_id))
+            .collect()
+    }
+
+    fn pin_levels(&self) -> Vec<bool> {
+        self.levels(&self.core.borrow().pin_states())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Simulator {
+    pub fn add_mcu(&mut self, board: &'static Board, core: Rc<RefCell<dyn McuCore>>) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let element = Rc::new(RefCell::new(Mcu::new(id, board, core)));
+        self.elements.insert(id, element);
+        id
+    }
+
+    pub fn mcu_program(&mut self, element_id: usize, board: &Board, hex: String) {
+        match self
+            .elements
+            .get(&element_id)
+            .unwrap()
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<Mcu>()
+        {
+            Some(mcu) if mcu.board() == board => mcu.program(hex),
+            _ => panic!("is not {}", board.name),
+        }
+    }
+
+    pub fn mcu_program_elf(&mut self, element_id: usize, elf: &[u8]) -> Result<(), String> {
+        match self
+            .elements
+            .get(&element_id)
+            .ok_or("no such element".to_string())?
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<Mcu>()
+        {
+            Some(mcu) => mcu.program_elf(elf),
+            None => Err("is not MCU".to_string()),
+        }
+    }
+
+    // Flash 上のバイトアドレスを関数名に変換する
+    pub fn mcu_symbolize(&self, element_id: usize, address: u32) -> Option<String> {
+        self.elements
+            .get(&element_id)?
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<Mcu>()?
+            .symbols()
+            .symbolize(address)
+    }
+}
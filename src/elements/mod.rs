@@ -1,5 +1,17 @@
+pub mod arduino_nano;
 pub mod arduino_uno;
+pub mod battery;
+pub mod bjt;
+pub mod controlled_src;
 pub mod diode;
 pub mod element;
+pub mod ind_current_src;
 pub mod ind_voltage_src;
+pub mod led;
+pub mod mcu;
+pub mod mosfet;
+pub mod opamp;
+pub mod potentiometer;
 pub mod registor;
+pub mod switch;
+pub mod waveform;
@@ -0,0 +1,363 @@
+use super::super::simulator::*;
+use super::element::*;
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// ドレイン - ソース間に並列に入れる微小コンダクタンス. 遮断状態でも方程式が解けるようにする.
+const GMIN: f32 = 1e-12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    N,
+    P,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MosfetLevel {
+    // Shichman-Hodges (SPICE Level 1)
+    Level1,
+    // EKV. 弱反転から強反転まで連続なモデル.
+    Ekv,
+}
+
+// MOSFET のモデルパラメータ
+//   ・P チャネルも VTO は正の値で指定する（極性は Channel で反転する）.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MosfetModel {
+    pub level: MosfetLevel,
+    // しきい値電圧 [V]
+    pub vto: f32,
+    // 相互コンダクタンスパラメータ [A/V^2]
+    pub kp: f32,
+    // チャネル幅 / チャネル長
+    pub w: f32,
+    pub l: f32,
+    // チャネル長変調 [1/V]
+    pub lambda: f32,
+    // 基板効果係数 [V^0.5] と表面ポテンシャル [V]
+    pub gamma: f32,
+    pub phi: f32,
+    // EKV の傾き係数
+    pub n: f32,
+}
+
+impl Default for MosfetModel {
+    // ロジックレベルのスイッチング用 MOSFET 程度の値
+    fn default() -> MosfetModel {
+        MosfetModel {
+            level: MosfetLevel::Level1,
+            vto: 1.5,
+            kp: 0.5,
+            w: 1.0,
+            l: 1.0,
+            lambda: 0.01,
+            gamma: 0.0,
+            phi: 0.6,
+            n: 1.3,
+        }
+    }
+}
+
+// MOSFET
+//   pins[0]: Drain,  pins[1]: Gate,  pins[2]: Source,  pins[3]: Bulk
+//   ・Bulk を接続しない場合は Source に接続されているものとする (3 端子).
+#[derive(Debug)]
+pub struct Mosfet {
+    channel: Channel,
+    pins: [usize; 3],
+    bulk: Option<usize>,
+    model: MosfetModel,
+}
+
+impl Mosfet {
+    pub fn new(channel: Channel, model: MosfetModel) -> Mosfet {
+        Mosfet {
+            channel,
+            pins: [0, 0, 0],
+            bulk: None,
+            model,
+        }
+    }
+
+    pub fn set_model(&mut self, model: MosfetModel) {
+        self.model = model;
+    }
+
+    fn sign(&self) -> f32 {
+        match self.channel {
+            Channel::N => 1.0,
+            Channel::P => -1.0,
+        }
+    }
+
+    fn nodes(&self) -> [usize; 4] {
+        [
+            self.pins[0],
+            self.pins[1],
+            self.pins[2],
+            self.bulk.unwrap_or(self.pins[2]),
+        ]
+    }
+
+    // Level 1: Vds >= 0 でのドレイン電流と (gm, gds, gmbs)
+    fn level1(&self, vgs: f32, vds: f32, vbs: f32) -> (f32, f32, f32, f32) {
+        let m = &self.model;
+        let beta = m.kp * m.w / m.l;
+
+        // 基板効果
+        let sarg = (m.phi - vbs).max(0.0).sqrt();
+        let vth = m.vto + m.gamma * (sarg - m.phi.sqrt());
+        let dvth_dvbs = if sarg > 0.0 {
+            -m.gamma / (2.0 * sarg)
+        } else {
+            0.0
+        };
+
+        let vov = vgs - vth;
+        let clm = 1.0 + m.lambda * vds;
+        let (id, gm, gds) = if vov <= 0.0 {
+            // 遮断
+            (0.0, 0.0, 0.0)
+        } else if vds < vov {
+            // 線形領域
+            let id0 = beta * (vov * vds - vds * vds / 2.0);
+            (
+                id0 * clm,
+                beta * vds * clm,
+                beta * (vov - vds) * clm + id0 * m.lambda,
+            )
+        } else {
+            // 飽和領域
+            let id0 = beta / 2.0 * vov * vov;
+            (id0 * clm, beta * vov * clm, id0 * m.lambda)
+        };
+        (id, gm, gds, -gm * dvth_dvbs)
+    }
+
+    // EKV: Bulk 基準の電圧 (Vg, Vd, Vs) でのドレイン電流と (dId/dVg, dId/dVd, dId/dVs)
+    fn ekv(&self, vg: f32, vd: f32, vs: f32) -> (f32, f32, f32, f32) {
+        let m = &self.model;
+        let beta = m.kp * m.w / m.l;
+        let ispec = 2.0 * m.n * beta * VT * VT;
+        let vp = (vg - m.vto) / m.n;
+
+        // F(v) = ln^2(1 + exp(v / 2Vt))
+        let f = |v: f32| {
+            let u = v / (2.0 * VT);
+            let ln = if u > 20.0 { u } else { u.exp().ln_1p() };
+            let sigmoid = 1.0 / (1.0 + (-u).exp());
+            (ln * ln, ln * sigmoid / VT)
+        };
+        let (i_f, dif) = f(vp - vs);
+        let (i_r, dir) = f(vp - vd);
+        let clm = 1.0 + m.lambda * (vd - vs).abs();
+        let id = ispec * (i_f - i_r) * clm;
+        (
+            id,
+            ispec * (dif - dir) * clm / m.n,
+            ispec * dir * clm,
+            -ispec * dif * clm,
+        )
+    }
+
+    // ドレイン電流と、各端子 (D, G, S, B) の電圧による偏微分
+    //   ・電圧・電流は N チャネルに換算したもの.
+    fn drain_current(&self, v: [f32; 4]) -> (f32, [f32; 4]) {
+        let [vd, vg, vs, vb] = v;
+        match self.model.level {
+            MosfetLevel::Level1 => {
+                if vd >= vs {
+                    let (id, gm, gds, gmbs) = self.level1(vg - vs, vd - vs, vb - vs);
+                    (id, [gds, gm, -(gm + gds + gmbs), gmbs])
+                } else {
+                    // ドレインとソースを入れ替えて計算する
+                    let (id, gm, gds, gmbs) = self.level1(vg - vd, vs - vd, vb - vd);
+                    (-id, [gm + gds + gmbs, -gm, -gds, -gmbs])
+                }
+            }
+            MosfetLevel::Ekv => {
+                let (id, gg, gd, gs) = self.ekv(vg - vb, vd - vb, vs - vb);
+                (id, [gd, gg, gs, -(gg + gd + gs)])
+            }
+        }
+    }
+}
+
+impl Element for Mosfet {
+    fn kind(&self) -> &'static str {
+        match self.channel {
+            Channel::N => "nmos",
+            Channel::P => "pmos",
+        }
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        match self.bulk {
+            Some(_) => vec!["drain", "gate", "source", "bulk"],
+            None => vec!["drain", "gate", "source"],
+        }
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        let mut nodes = self.pins.to_vec();
+        nodes.extend(self.bulk);
+        nodes
+    }
+
+    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
+        match pin_id {
+            3 => self.bulk = Some(node_id),
+            _ => self.pins[pin_id] = node_id,
+        }
+    }
+
+    fn stamp(&self, eq: &mut Equation) {
+        let nodes = self.nodes();
+        let index: Vec<Option<usize>> = nodes.iter().map(|node_id| eq.node(*node_id)).collect();
+
+        // Id = Σ g_k * V_k + I_eq と線形化する
+        let p = self.sign();
+        let mut v = [0.0; 4];
+        for k in 0..4 {
+            v[k] = p * eq.value(index[k]);
+        }
+        let (id, g) = self.drain_current(v);
+        let i_eq = p * (id - (0..4).map(|k| g[k] * v[k]).sum::<f32>());
+
+        for k in 0..4 {
+            eq.add_a(index[0], index[k], g[k]);
+            eq.add_a(index[2], index[k], -g[k]);
+        }
+        eq.add_z(index[0], -i_eq);
+        eq.add_z(index[2], i_eq);
+
+        eq.stamp_conductance_index(index[0], index[2], GMIN);
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        let p = self.sign();
+        let nodes = self.nodes();
+        let mut v = [0.0; 4];
+        for k in 0..4 {
+            v[k] = p * eq.voltage(nodes[k]);
+        }
+        let (id, _) = self.drain_current(v);
+        vec![p * id, 0.0, -p * id, 0.0]
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            param("vto", "V", 0.0, INF),
+            param("kp", "A/V^2", 1e-9, INF),
+            param("w", "", 1e-9, INF),
+            param("l", "", 1e-9, INF),
+            param("lambda", "1/V", 0.0, INF),
+            param("gamma", "V^0.5", 0.0, INF),
+            param("phi", "V", 0.01, INF),
+            param("n", "", 1.0, INF),
+        ]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        let m = &self.model;
+        match name {
+            "vto" => Some(m.vto),
+            "kp" => Some(m.kp),
+            "w" => Some(m.w),
+            "l" => Some(m.l),
+            "lambda" => Some(m.lambda),
+            "gamma" => Some(m.gamma),
+            "phi" => Some(m.phi),
+            "n" => Some(m.n),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        let mut m = self.model.clone();
+        match name {
+            "vto" => m.vto = value,
+            "kp" => m.kp = value,
+            "w" => m.w = value,
+            "l" => m.l = value,
+            "lambda" => m.lambda = value,
+            "gamma" => m.gamma = value,
+            "phi" => m.phi = value,
+            "n" => m.n = value,
+            _ => return Err(format!("unknown parameter: {}", name)),
+        }
+        self.set_model(m);
+        Ok(())
+    }
+
+    // 領域は Level 1 の式（基板効果を除く）で判定する.
+    fn operating_point(&self, eq: &Equation) -> Option<(&'static str, ElementState)> {
+        let nodes = self.nodes();
+        let mut v = [0.0; 4];
+        for i in 0..4 {
+            v[i] = self.sign() * eq.voltage(nodes[i]);
+        }
+        let (id, g) = self.drain_current(v);
+
+        // ドレインとソースは電位の高い方をドレインとみなす
+        let [vd, vg, vs, _] = v;
+        let (vgs, vds) = if vd >= vs {
+            (vg - vs, vd - vs)
+        } else {
+            (vg - vd, vs - vd)
+        };
+        let vov = vgs - self.model.vto;
+        let region = if vov <= 0.0 {
+            "cutoff"
+        } else if vds < vov {
+            "triode"
+        } else {
+            "saturation"
+        };
+
+        let mut params = ElementState::new();
+        params.insert("id", self.sign() * id);
+        params.insert("vgs", vgs);
+        params.insert("vds", vds);
+        params.insert("gm", g[1].abs());
+        params.insert("gds", g[0].abs());
+        Some((region, params))
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Simulator {
+    fn add_mosfet(&mut self, channel: Channel) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let element = Rc::new(RefCell::new(Mosfet::new(channel, MosfetModel::default())));
+        self.elements.insert(id, element);
+        id
+    }
+
+    pub fn add_nmos(&mut self) -> usize {
+        self.add_mosfet(Channel::N)
+    }
+
+    pub fn add_pmos(&mut self) -> usize {
+        self.add_mosfet(Channel::P)
+    }
+
+    pub fn mosfet_set_model(&mut self, element_id: usize, model: MosfetModel) {
+        match self
+            .elements
+            .get(&element_id)
+            .unwrap()
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<Mosfet>()
+        {
+            Some(mosfet) => mosfet.set_model(model),
+            None => panic!("is not Mosfet"),
+        }
+        self.state = None;
+    }
+}
@@ -0,0 +1,345 @@
+use super::super::ac::AcEquation;
+use super::super::simulator::*;
+use super::element::*;
+use nalgebra::Complex;
+use std::any::Any;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// オペアンプのモデルパラメータ
+//   ・ideal の場合は仮想短絡 (V+ = V-) だけを課し、他のパラメータは入力オフセット以外使わない.
+//   ・gbw は直流解析では使わない（AC 解析で 1 次の極として使う）.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpAmpModel {
+    pub ideal: bool,
+    // 開ループ利得
+    pub gain: f32,
+    // 利得帯域幅積 [Hz]
+    pub gbw: f32,
+    // 入力オフセット電圧 [V]
+    pub vos: f32,
+    // 出力電圧の範囲 [V]
+    pub rail_low: f32,
+    pub rail_high: f32,
+    // 出力抵抗 [Ω] と出力電流の上限 [A]
+    pub r_out: f32,
+    pub i_limit: f32,
+}
+
+impl Default for OpAmpModel {
+    // 5V 単電源のレール・ツー・レールのオペアンプ程度の値
+    fn default() -> OpAmpModel {
+        OpAmpModel {
+            ideal: false,
+            gain: 100_000.0,
+            gbw: 1_000_000.0,
+            vos: 0.0,
+            rail_low: 0.0,
+            rail_high: 5.0,
+            r_out: 75.0,
+            i_limit: 0.025,
+        }
+    }
+}
+
+// オペアンプ
+//   pins[0]: 非反転入力 (+),  pins[1]: 反転入力 (-),  pins[2]: 出力
+//   ・出力ピンは src となり、出力に流れ込む電流を方程式の変数に持つ.
+#[derive(Debug)]
+pub struct OpAmp {
+    id: usize,
+    pins: [usize; 3],
+    model: OpAmpModel,
+    // 前回の stamp での（制限前の）差動入力と出力電流. 飽和に入る前に一度線形領域の端で止めるために使う.
+    last: Cell<(f32, f32)>,
+    limited: Cell<bool>,
+    // 前回の stamp で出力電圧を制限した値. 制限しなかった場合は None.
+    clamped: Cell<Option<f32>>,
+    // 最後の stamp で出力電流の行に押した差動入力の係数. AC 解析で使う.
+    differential: Cell<f32>,
+}
+
+// Newton 法の途中で出力電圧を評価する範囲（レールからの余裕 [V]）
+const OUTPUT_LIMIT_MARGIN: f32 = 1.0;
+
+impl OpAmp {
+    pub fn new(id: usize, model: OpAmpModel) -> OpAmp {
+        OpAmp {
+            id,
+            pins: [0, 0, 0],
+            model,
+            last: Cell::new((0.0, 0.0)),
+            limited: Cell::new(false),
+            clamped: Cell::new(None),
+            differential: Cell::new(0.0),
+        }
+    }
+
+    // 出力電圧の範囲が逆転するモデルは線形領域も逆転するので、エラーにする
+    pub fn set_model(&mut self, model: OpAmpModel) -> Result<(), String> {
+        if model.rail_high <= model.rail_low {
+            return Err("rail_high must be greater than rail_low".to_string());
+        }
+        self.model = model;
+        Ok(())
+    }
+
+    // 開ループ出力が線形な差動入力の範囲
+    fn linear_range(&self) -> (f32, f32) {
+        let m = &self.model;
+        let half = (m.rail_high - m.rail_low) / 2.0;
+        (-half / m.gain, half / m.gain)
+    }
+
+    // 差動入力 vd に対する内部の出力電圧とその微分. レールで飽和する.
+    fn open_loop(&self, vd: f32) -> (f32, f32) {
+        let m = &self.model;
+        let mid = (m.rail_high + m.rail_low) / 2.0;
+        saturate(mid + m.gain * vd, m.rail_low, m.rail_high, m.gain)
+    }
+}
+
+// 前回は線形領域 [low, high] にあり、今回その外に出る場合は一度だけ端で止める.
+//   ・飽和した点で線形化すると帰還が見えなくなるので、端の利得で一度 Newton 法を進める.
+//     次の反復でも外に出るならそのまま飽和させる.
+fn limit_edge(new: f32, last: f32, low: f32, high: f32) -> (f32, bool) {
+    let is_inside = |v: f32| low <= v && v <= high;
+    if is_inside(last) && !is_inside(new) {
+        (new.clamp(low, high), true)
+    } else {
+        (new, false)
+    }
+}
+
+// 飽和の外側に残す傾き. 0 にすると Newton 法で帰還が効かなくなるので、わずかに残す.
+const SATURATED_SLOPE: f32 = 1e-8;
+
+// v を [low, high] に区分線形で飽和させる. dv は v の微分.
+//   ・tanh などの滑らかな関数では、飽和の奥で微分がほぼ 0 になり Newton 法が振動する.
+fn saturate(v: f32, low: f32, high: f32, dv: f32) -> (f32, f32) {
+    if v > high {
+        (high + (v - high) * SATURATED_SLOPE, dv * SATURATED_SLOPE)
+    } else if v < low {
+        (low + (v - low) * SATURATED_SLOPE, dv * SATURATED_SLOPE)
+    } else {
+        (v, dv)
+    }
+}
+
+impl Element for OpAmp {
+    fn kind(&self) -> &'static str {
+        if self.model.ideal {
+            "ideal_opamp"
+        } else {
+            "opamp"
+        }
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        vec!["in+", "in-", "out"]
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.to_vec()
+    }
+
+    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
+        self.pins[pin_id] = node_id;
+    }
+
+    fn output_pins(&self) -> Vec<bool> {
+        vec![false, false, true]
+    }
+
+    fn stamp(&self, eq: &mut Equation) {
+        let p = eq.node(self.pins[0]);
+        let n = eq.node(self.pins[1]);
+        let out = eq.node(self.pins[2]);
+        let k = eq.src(self.id, 2);
+        let m = &self.model;
+
+        // 出力ノードの KCL に出力電流を加える
+        eq.add_a(out, k, 1.0);
+
+        if m.ideal {
+            // 仮想短絡: V+ - V- + Vos = 0
+            eq.add_a(k, p, 1.0);
+            eq.add_a(k, n, -1.0);
+            eq.add_z(k, -m.vos);
+            return;
+        }
+
+        // 出力電流 I = -clamp((Vo' - Vout) / Rout, ±Ilim)
+        //   ・Vo' は開ループ出力. 出力抵抗 Rout を通して出力し、電流は ±Ilim に制限される.
+        //   ・g(x) = I + clamp(..) = 0 を Newton 法のために線形化する.
+        //   ・反復の途中で出力電圧がレールから大きく外れると電流制限の間を振動するので、
+        //     レール付近に制限して評価する.
+        //     続けて同じ値に制限される（出力が外部から固定されている）場合は、制限をやめて
+        //     実際の出力電圧で評価する.
+        //   ・反復の途中で飽和に入るときは、一度線形領域の端で止める.
+        let (vd_last, i_last) = self.last.get();
+        let (low, high) = self.linear_range();
+        let vd_new = eq.value(p) - eq.value(n) + m.vos;
+        let (vd, limited_vd) = limit_edge(vd_new, vd_last, low, high);
+        let vout_new = eq.value(out);
+        let bound = vout_new.clamp(
+            m.rail_low - OUTPUT_LIMIT_MARGIN,
+            m.rail_high + OUTPUT_LIMIT_MARGIN,
+        );
+        let clamped = if bound != vout_new { Some(bound) } else { None };
+        let released = clamped.is_some() && self.clamped.replace(clamped) == clamped;
+        let vout = if released { vout_new } else { bound };
+        let limited_out = vout != vout_new;
+        let (vo, dvo) = self.open_loop(vd);
+        let i_new = (vo - vout) / m.r_out;
+        let (i, limited_i) = limit_edge(i_new, i_last, -m.i_limit, m.i_limit);
+        let (i, di) = saturate(i, -m.i_limit, m.i_limit, 1.0 / m.r_out);
+        self.last.set((vd_new, i_new));
+        self.limited.set(limited_out || limited_vd || limited_i);
+
+        let g = eq.value(k) + i;
+        let d_vd = di * dvo;
+        let d_vout = -di;
+        let jx = eq.value(k) + d_vd * (vd - m.vos) + d_vout * vout;
+
+        // 利得が大きいと f32 の丸め誤差で残差が収束判定より大きくなるので、行を正規化する.
+        let scale = 1.0 / d_vd.abs().max(1.0);
+        eq.add_a(k, k, scale);
+        self.differential.set(d_vd * scale);
+        eq.add_a(k, p, d_vd * scale);
+        eq.add_a(k, n, -d_vd * scale);
+        eq.add_a(k, out, d_vout * scale);
+        eq.add_z(k, (jx - g) * scale);
+    }
+
+    fn limited(&self) -> bool {
+        self.limited.get()
+    }
+
+    // 開ループ利得を A(f) = gain / (1 + j f gain / gbw) とする.
+    //   ・出力電流の行の差動入力の係数を 1 / (1 + j f gain / gbw) 倍する.
+    //     入力と出力が同じノードの場合（フォロワ）もあるので、差動入力の分だけを書き換える.
+    fn stamp_ac(&self, eq: &Equation, ac: &mut AcEquation) {
+        let m = &self.model;
+        let k = match eq.src(self.id, 2) {
+            Some(k) if !m.ideal => k,
+            _ => return,
+        };
+        let pole = Complex::new(1.0, ac.frequency * m.gain as f64 / m.gbw as f64);
+        let c = self.differential.get() as f64;
+        let delta = c / pole - c;
+        if let Some(p) = eq.node(self.pins[0]) {
+            ac.a[(k, p)] += delta;
+        }
+        if let Some(n) = eq.node(self.pins[1]) {
+            ac.a[(k, n)] -= delta;
+        }
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        vec![0.0, 0.0, eq.src_current(self.id, 2)]
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            param("gain", "", 1.0, INF),
+            param("gbw", "Hz", 1.0, INF),
+            param("vos", "V", -INF, INF),
+            param("rail_low", "V", -INF, INF),
+            param("rail_high", "V", -INF, INF),
+            param("r_out", "Ω", 0.0, INF),
+            param("i_limit", "A", 1e-6, INF),
+        ]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        let m = &self.model;
+        match name {
+            "gain" => Some(m.gain),
+            "gbw" => Some(m.gbw),
+            "vos" => Some(m.vos),
+            "rail_low" => Some(m.rail_low),
+            "rail_high" => Some(m.rail_high),
+            "r_out" => Some(m.r_out),
+            "i_limit" => Some(m.i_limit),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        let mut m = self.model.clone();
+        match name {
+            "gain" => m.gain = value,
+            "gbw" => m.gbw = value,
+            "vos" => m.vos = value,
+            "rail_low" => m.rail_low = value,
+            "rail_high" => m.rail_high = value,
+            "r_out" => m.r_out = value,
+            "i_limit" => m.i_limit = value,
+            _ => return Err(format!("unknown parameter: {}", name)),
+        }
+        self.set_model(m)
+    }
+
+    fn operating_point(&self, eq: &Equation) -> Option<(&'static str, ElementState)> {
+        let m = &self.model;
+        let vd = eq.voltage(self.pins[0]) - eq.voltage(self.pins[1]) + m.vos;
+        let (low, high) = self.linear_range();
+        let region = if m.ideal || (low <= vd && vd <= high) {
+            "linear"
+        } else if vd > high {
+            "saturated_high"
+        } else {
+            "saturated_low"
+        };
+        let mut params = ElementState::new();
+        params.insert("vd", vd);
+        params.insert("gain", if region == "linear" { m.gain } else { 0.0 });
+        params.insert("r_out", m.r_out);
+        Some((region, params))
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Simulator {
+    fn add_opamp_with_model(&mut self, model: OpAmpModel) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let element = Rc::new(RefCell::new(OpAmp::new(id, model)));
+        self.elements.insert(id, element);
+        id
+    }
+
+    // 理想オペアンプ
+    pub fn add_ideal_opamp(&mut self) -> usize {
+        let model = OpAmpModel {
+            ideal: true,
+            ..Default::default()
+        };
+        self.add_opamp_with_model(model)
+    }
+
+    // 有限利得のオペアンプ（マクロモデル）
+    pub fn add_opamp(&mut self) -> usize {
+        self.add_opamp_with_model(OpAmpModel::default())
+    }
+
+    pub fn opamp_set_model(&mut self, element_id: usize, model: OpAmpModel) -> Result<(), String> {
+        match self
+            .elements
+            .get(&element_id)
+            .unwrap()
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<OpAmp>()
+        {
+            Some(opamp) => opamp.set_model(model)?,
+            None => panic!("is not OpAmp"),
+        }
+        self.state = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use super::super::simulator::*;
+use super::element::*;
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 抵抗値が 0 になると方程式が解けなくなるので、最小値を設ける
+const MIN_REGISTANCE: f32 = 0.01;
+// A カーブ（対数）の底. 中点で全抵抗の 10% になる.
+const LOG_TAPER_BASE: f32 = 81.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Taper {
+    // B カーブ（直線）
+    Linear,
+    // A カーブ（対数）
+    Log,
+}
+
+// 可変抵抗器
+//   ・pins[0]-pins[1] (端子 - ワイパー), pins[1]-pins[2] (ワイパー - 端子) の 2 つの抵抗として扱う.
+#[derive(Debug)]
+pub struct Potentiometer {
+    // pins[0]: 端子 1,  pins[1]: ワイパー,  pins[2]: 端子 3
+    pins: [usize; 3],
+    resistance: f32,
+    // ワイパーの位置. 0 で端子 1 側, 1 で端子 3 側.
+    position: f32,
+    taper: Taper,
+}
+
+impl Potentiometer {
+    pub fn new(registance: f32, taper: Taper) -> Potentiometer {
+        Potentiometer {
+            pins: [0, 0, 0],
+            resistance: registance,
+            position: 0.5,
+            taper,
+        }
+    }
+
+    // 端子 1 - ワイパー間の抵抗の、全抵抗に対する割合
+    fn ratio(&self) -> f32 {
+        match self.taper {
+            Taper::Linear => self.position,
+            Taper::Log => (LOG_TAPER_BASE.powf(self.position) - 1.0) / (LOG_TAPER_BASE - 1.0),
+        }
+    }
+
+    // (端子 1 - ワイパー, ワイパー - 端子 3) のコンダクタンス
+    fn conductances(&self) -> (f32, f32) {
+        let r0 = (self.resistance * self.ratio()).max(MIN_REGISTANCE);
+        let r1 = (self.resistance * (1.0 - self.ratio())).max(MIN_REGISTANCE);
+        (1.0 / r0, 1.0 / r1)
+    }
+
+    pub fn change_position(&mut self, position: f32) {
+        self.position = position.clamp(0.0, 1.0);
+    }
+}
+
+impl Element for Potentiometer {
+    fn kind(&self) -> &'static str {
+        match self.taper {
+            Taper::Linear => "potentiometer",
+            Taper::Log => "log_potentiometer",
+        }
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        vec!["end1", "wiper", "end3"]
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.to_vec()
+    }
+
+    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
+        self.pins[pin_id] = node_id;
+    }
+
+    fn stamp(&self, eq: &mut Equation) {
+        let (g0, g1) = self.conductances();
+        eq.stamp_conductance(self.pins[0], self.pins[1], g0);
+        eq.stamp_conductance(self.pins[1], self.pins[2], g1);
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        let (g0, g1) = self.conductances();
+        let i0 = (eq.voltage(self.pins[0]) - eq.voltage(self.pins[1])) * g0;
+        let i1 = (eq.voltage(self.pins[1]) - eq.voltage(self.pins[2])) * g1;
+        vec![i0, i1 - i0, -i1]
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            param("resistance", "Ω", MIN_REGISTANCE, INF),
+            param("position", "", 0.0, 1.0),
+        ]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        match name {
+            "resistance" => Some(self.resistance),
+            "position" => Some(self.position),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "resistance" => self.resistance = value,
+            "position" => self.change_position(value),
+            _ => return Err(format!("unknown parameter: {}", name)),
+        }
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Simulator {
+    fn add_potentiometer_with_taper(&mut self, r: f32, taper: Taper) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let element = Rc::new(RefCell::new(Potentiometer::new(r, taper)));
+        self.elements.insert(id, element);
+        id
+    }
+
+    pub fn add_potentiometer(&mut self, r: f32) -> usize {
+        self.add_potentiometer_with_taper(r, Taper::Linear)
+    }
+
+    pub fn add_log_potentiometer(&mut self, r: f32) -> usize {
+        self.add_potentiometer_with_taper(r, Taper::Log)
+    }
+
+    fn with_potentiometer<F: FnOnce(&mut Potentiometer)>(&mut self, element_id: usize, f: F) {
+        match self
+            .elements
+            .get(&element_id)
+            .unwrap()
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<Potentiometer>()
+        {
+            Some(potentiometer) => f(potentiometer),
+            None => panic!("is not Potentiometer"),
+        }
+    }
+
+    // ワイパーの位置 (0..1) を変化させる
+    pub fn potentiometer_change_position(&mut self, element_id: usize, position: f32) {
+        self.with_potentiometer(element_id, |p| p.change_position(position));
+    }
+
+    // 全抵抗値を変化させる. 範囲外の値はエラー.
+    pub fn potentiometer_change_registance(
+        &mut self,
+        element_id: usize,
+        r: f32,
+    ) -> Result<(), String> {
+        self.set_param(element_id, "resistance", r)
+    }
+}
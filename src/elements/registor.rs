@@ -17,7 +17,7 @@ impl Registor {
     pub fn new(id: usize, registance: f32) -> Registor {
         let r = if registance == 0.0 { 0.01 } else { registance };
         Registor {
-            id: id,
+            id,
             pins: [0, 0],
             resistance: r,
         }
@@ -33,29 +33,48 @@ impl Registor {
 }
 
 impl Element for Registor {
+    fn kind(&self) -> &'static str {
+        "registor"
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        vec!["1", "2"]
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.to_vec()
+    }
+
     fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
         self.pins[pin_id] = node_id;
     }
 
     fn stamp(&self, eq: &mut Equation) {
-        match self.pins {
-            [p0, 0] => {
-                let p0 = *eq.node_index.get(&p0).unwrap();
-                eq.a[(p0, p0)] += self.conductance();
-            }
-            [0, p1] => {
-                let p1 = *eq.node_index.get(&p1).unwrap();
-                eq.a[(p1, p1)] += self.conductance();
-            }
-            [p0, p1] => {
-                let p0 = *eq.node_index.get(&p0).unwrap();
-                let p1 = *eq.node_index.get(&p1).unwrap();
-                eq.a[(p0, p0)] += self.conductance();
-                eq.a[(p1, p1)] += self.conductance();
-                eq.a[(p0, p1)] -= self.conductance();
-                eq.a[(p1, p0)] -= self.conductance();
-            }
+        eq.stamp_conductance(self.pins[0], self.pins[1], self.conductance());
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        let i = (eq.voltage(self.pins[0]) - eq.voltage(self.pins[1])) * self.conductance();
+        vec![i, -i]
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![param("resistance", "Ω", 0.01, INF)]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        match name {
+            "resistance" => Some(self.resistance),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "resistance" => self.change_registance(value),
+            _ => return Err(format!("unknown parameter: {}", name)),
         }
+        Ok(())
     }
 
     fn as_any(&mut self) -> &mut dyn Any {
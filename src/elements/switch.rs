@@ -0,0 +1,327 @@
+use super::super::simulator::*;
+use super::element::*;
+use serde::*;
+use std::any::Any;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// チャタリングで接点が開閉を繰り返す周期 [s]
+const BOUNCE_PERIOD: f64 = 0.0001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwitchKind {
+    // 単極単投. pins[0]-pins[1] が ON で導通する.
+    Spst,
+    // 単極双投. ON で pins[0]-pins[1], OFF で pins[0]-pins[2] が導通する.
+    Spdt,
+    // 押しボタン（モーメンタリ）. 押している間だけ pins[0]-pins[1] が導通する.
+    //   ・press で押した場合は、指定した時間が経つと自動的に離される.
+    Pushbutton,
+}
+
+// スイッチ
+//   ・接点は ON 抵抗 / OFF 抵抗を持つ抵抗としてモデリングする.
+//   ・状態を切り替えた直後の bounce [s] の間は、接点が BOUNCE_PERIOD ごとに開閉を繰り返す.
+#[derive(Debug)]
+pub struct Switch {
+    kind: SwitchKind,
+    pins: Vec<usize>,
+    r_on: f32,
+    r_off: f32,
+    // 操作された状態
+    state: Cell<bool>,
+    // 接点の状態. チャタリング中は state と異なることがある.
+    contact: Cell<bool>,
+    // 切り替えられてから、まだ clk で通知していない
+    changed: Cell<bool>,
+    // 状態を切り替えてからのクロック数
+    elapsed: Cell<u64>,
+    // チャタリングが続くクロック数. 0 の場合はチャタリングしない.
+    bounce: u64,
+    // 押しボタンが自動的に離されるまでのクロック数
+    hold: Cell<Option<u64>>,
+    // save した時点の (state, contact, changed, elapsed, hold)
+    saved: Option<(bool, bool, bool, u64, Option<u64>)>,
+    // save した後に操作された
+    operated: bool,
+}
+
+impl Switch {
+    pub fn new(kind: SwitchKind) -> Switch {
+        let pin_count = match kind {
+            SwitchKind::Spdt => 3,
+            _ => 2,
+        };
+        Switch {
+            kind,
+            pins: vec![0; pin_count],
+            r_on: 0.01,
+            r_off: 100_000_000.0,
+            state: Cell::new(false),
+            contact: Cell::new(false),
+            changed: Cell::new(false),
+            elapsed: Cell::new(0),
+            bounce: 0,
+            hold: Cell::new(None),
+            saved: None,
+            operated: false,
+        }
+    }
+
+    pub fn set_state(&mut self, on: bool) {
+        self.operated = true;
+        self.hold.set(None);
+        self.switch(on);
+    }
+
+    // 押しボタンを押し、duration [s] 後に離す
+    pub fn press(&mut self, duration: f64) -> Result<(), String> {
+        if self.kind != SwitchKind::Pushbutton {
+            return Err("is not Pushbutton".to_string());
+        }
+        self.set_state(true);
+        let cycles = (duration.max(0.0) * CLOCK_FREQUENCY).round() as u64;
+        self.hold.set(Some(cycles));
+        Ok(())
+    }
+
+    fn switch(&self, on: bool) {
+        if self.state.get() != on {
+            self.state.set(on);
+            self.contact.set(on);
+            self.changed.set(true);
+            self.elapsed.set(0);
+        }
+    }
+
+    pub fn set_bounce(&mut self, duration: f64) {
+        self.bounce = (duration.max(0.0) * CLOCK_FREQUENCY).round() as u64;
+    }
+
+    fn conductance(&self, closed: bool) -> f32 {
+        if closed {
+            1.0 / self.r_on
+        } else {
+            1.0 / self.r_off
+        }
+    }
+
+    // 各接点 (pins[0]-pins[n]) のコンダクタンス
+    fn conductances(&self) -> Vec<(usize, f32)> {
+        let contact = self.contact.get();
+        match self.kind {
+            SwitchKind::Spdt => vec![
+                (self.pins[1], self.conductance(contact)),
+                (self.pins[2], self.conductance(!contact)),
+            ],
+            _ => vec![(self.pins[1], self.conductance(contact))],
+        }
+    }
+}
+
+impl Element for Switch {
+    fn kind(&self) -> &'static str {
+        match self.kind {
+            SwitchKind::Spst => "spst_switch",
+            SwitchKind::Spdt => "spdt_switch",
+            SwitchKind::Pushbutton => "pushbutton",
+        }
+    }
+
+    fn pin_names(&self) -> Vec<&'static str> {
+        match self.kind {
+            SwitchKind::Spdt => vec!["common", "on", "off"],
+            _ => vec!["1", "2"],
+        }
+    }
+
+    fn pin_nodes(&self) -> Vec<NodeId> {
+        self.pins.clone()
+    }
+
+    fn connect_pin_to_node(&mut self, pin_id: usize, node_id: usize) {
+        self.pins[pin_id] = node_id;
+    }
+
+    fn stamp(&self, eq: &mut Equation) {
+        for (node_id, g) in self.conductances() {
+            eq.stamp_conductance(self.pins[0], node_id, g);
+        }
+    }
+
+    // 切り替えられた直後と、チャタリング中に接点の開閉が変化したときに true を返す
+    fn clk(&self) -> bool {
+        // 押しボタンを離す時刻になった
+        match self.hold.get() {
+            Some(0) => {
+                self.hold.set(None);
+                self.switch(false);
+            }
+            Some(remaining) => self.hold.set(Some(remaining - 1)),
+            None => (),
+        }
+
+        let changed = self.changed.replace(false);
+        let elapsed = self.elapsed.get();
+        if elapsed >= self.bounce {
+            return changed;
+        }
+        self.elapsed.set(elapsed + 1);
+
+        let period = (BOUNCE_PERIOD * CLOCK_FREQUENCY) as u64;
+        let contact = if elapsed + 1 >= self.bounce || ((elapsed + 1) / period).is_multiple_of(2) {
+            self.state.get()
+        } else {
+            !self.state.get()
+        };
+        if contact != self.contact.get() {
+            self.contact.set(contact);
+            true
+        } else {
+            changed
+        }
+    }
+
+    fn save(&mut self) -> Result<(), String> {
+        self.saved = Some((
+            self.state.get(),
+            self.contact.get(),
+            self.changed.get(),
+            self.elapsed.get(),
+            self.hold.get(),
+        ));
+        self.operated = false;
+        Ok(())
+    }
+
+    // チャタリングや押しボタンを押している途中の状態に戻す. save した後に操作された場合はその操作を優先する.
+    fn rewind(&mut self, _cycle: u64) {
+        if let Some((state, contact, changed, elapsed, hold)) = self.saved {
+            if !self.operated {
+                self.state.set(state);
+                self.contact.set(contact);
+                self.changed.set(changed);
+                self.elapsed.set(elapsed);
+                self.hold.set(hold);
+            }
+        }
+    }
+
+    fn currents(&self, eq: &Equation) -> Vec<f32> {
+        let mut currents = vec![0.0; self.pins.len()];
+        for (pin_id, (node_id, g)) in self.conductances().iter().enumerate() {
+            let i = (eq.voltage(self.pins[0]) - eq.voltage(*node_id)) * g;
+            currents[0] += i;
+            currents[pin_id + 1] -= i;
+        }
+        currents
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            // 0: 開, 1: 閉 (SPDT は 0: 端子 1 側, 1: 端子 2 側)
+            discrete_param("state", "", 0.0, 1.0),
+            param("r_on", "Ω", 1e-6, INF),
+            param("r_off", "Ω", 1e-6, INF),
+            param("bounce", "s", 0.0, INF),
+        ]
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        match name {
+            "state" => Some(if self.state.get() { 1.0 } else { 0.0 }),
+            "r_on" => Some(self.r_on),
+            "r_off" => Some(self.r_off),
+            "bounce" => Some((self.bounce as f64 / CLOCK_FREQUENCY) as f32),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "state" => self.set_state(value >= 0.5),
+            "r_on" => self.r_on = value,
+            "r_off" => self.r_off = value,
+            "bounce" => self.set_bounce(value as f64),
+            _ => return Err(format!("unknown parameter: {}", name)),
+        }
+        Ok(())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Simulator {
+    pub fn add_switch(&mut self, kind: SwitchKind) -> usize {
+        let id = self.elements.keys().max().unwrap_or(&0usize) + 1;
+        let element = Rc::new(RefCell::new(Switch::new(kind)));
+        self.elements.insert(id, element);
+        id
+    }
+
+    pub fn add_spst_switch(&mut self) -> usize {
+        self.add_switch(SwitchKind::Spst)
+    }
+
+    pub fn add_spdt_switch(&mut self) -> usize {
+        self.add_switch(SwitchKind::Spdt)
+    }
+
+    pub fn add_pushbutton(&mut self) -> usize {
+        self.add_switch(SwitchKind::Pushbutton)
+    }
+
+    fn with_switch<T, F: FnOnce(&mut Switch) -> T>(&mut self, element_id: usize, f: F) -> T {
+        match self
+            .elements
+            .get(&element_id)
+            .unwrap()
+            .borrow_mut()
+            .as_any()
+            .downcast_mut::<Switch>()
+        {
+            Some(switch) => f(switch),
+            None => panic!("is not Switch"),
+        }
+    }
+
+    // スイッチを切り替える. 押しボタンの場合は true で押す（押したままにする）, false で離す.
+    pub fn set_switch_state(&mut self, element_id: usize, on: bool) {
+        self.with_switch(element_id, |switch| switch.set_state(on));
+    }
+
+    // 押しボタンを押し、duration [s] 後に離す
+    pub fn press_pushbutton(&mut self, element_id: usize, duration: f64) -> Result<(), String> {
+        let element = self.element(element_id)?;
+        let mut element = element.borrow_mut();
+        match element.as_any().downcast_mut::<Switch>() {
+            Some(switch) => switch.press(duration),
+            None => Err(format!("is not Switch: {}", element_id)),
+        }
+    }
+
+    // ON 抵抗 / OFF 抵抗 [Ω] を変更する. 範囲外の場合はどちらも変更しない.
+    pub fn set_switch_resistance(
+        &mut self,
+        element_id: usize,
+        r_on: f32,
+        r_off: f32,
+    ) -> Result<(), String> {
+        let original = self.get_param(element_id, "r_on")?;
+        self.set_param(element_id, "r_on", r_on)?;
+        if let Err(err) = self.set_param(element_id, "r_off", r_off) {
+            self.set_param(element_id, "r_on", original)?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // チャタリングの時間 [s] を設定する. 0 でチャタリングしない.
+    pub fn set_switch_bounce(&mut self, element_id: usize, duration: f64) {
+        self.with_switch(element_id, |switch| switch.set_bounce(duration));
+    }
+}
@@ -0,0 +1,354 @@
+use super::super::ac::phasor;
+use super::super::simulator::*;
+use super::ind_current_src::IndCurrentSrc;
+use super::ind_voltage_src::IndVoltageSrc;
+use nalgebra::Complex;
+use std::cell::Cell;
+use std::f64::consts::PI;
+
+// 出力が波形の値の幅のこの割合以上変化したら方程式を解き直す
+//   ・SIN などはクロックごとに値が少しずつ変わるので、毎クロック解き直さない.
+const RESOLUTION: f32 = 0.001;
+
+// 独立電源の出力波形 (SPICE の過渡解析の波形指定に相当する)
+//   ・時間は [s]、値は電圧源なら [V]、電流源なら [A].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Waveform {
+    Dc(f32),
+    // PULSE(V1 V2 TD TR TF PW PER). per <= 0 の場合は 1 回だけ.
+    Pulse {
+        v1: f32,
+        v2: f32,
+        td: f64,
+        tr: f64,
+        tf: f64,
+        pw: f64,
+        per: f64,
+    },
+    // SIN(VO VA FREQ TD THETA PHASE). phase は [度].
+    Sin {
+        vo: f32,
+        va: f32,
+        freq: f64,
+        td: f64,
+        theta: f64,
+        phase: f64,
+    },
+    // EXP(V1 V2 TD1 TAU1 TD2 TAU2)
+    Exp {
+        v1: f32,
+        v2: f32,
+        td1: f64,
+        tau1: f64,
+        td2: f64,
+        tau2: f64,
+    },
+    // PWL(T1 V1 T2 V2 ...). 時刻の昇順に並べること.
+    Pwl(Vec<(f64, f32)>),
+    // SFFM(VO VA FC MDI FS)
+    Sffm {
+        vo: f32,
+        va: f32,
+        fc: f64,
+        mdi: f64,
+        fs: f64,
+    },
+}
+
+impl Waveform {
+    // 時刻 t における値
+    pub fn value(&self, t: f64) -> f32 {
+        match self {
+            Waveform::Dc(v) => *v,
+            Waveform::Pulse {
+                v1,
+                v2,
+                td,
+                tr,
+                tf,
+                pw,
+                per,
+            } => {
+                if t < *td {
+                    return *v1;
+                }
+                let tt = if *per > 0.0 { (t - td) % per } else { t - td };
+                let (v1, v2) = (*v1 as f64, *v2 as f64);
+                let v = if tt < *tr {
+                    v1 + (v2 - v1) * tt / tr
+                } else if tt < tr + pw {
+                    v2
+                } else if tt < tr + pw + tf {
+                    v2 + (v1 - v2) * (tt - tr - pw) / tf
+                } else {
+                    v1
+                };
+                v as f32
+            }
+            Waveform::Sin {
+                vo,
+                va,
+                freq,
+                td,
+                theta,
+                phase,
+            } => {
+                let phase = phase.to_radians();
+                if t < *td {
+                    return vo + va * phase.sin() as f32;
+                }
+                let tt = t - td;
+                let v = (-tt * theta).exp() * (2.0 * PI * freq * tt + phase).sin();
+                vo + va * v as f32
+            }
+            Waveform::Exp {
+                v1,
+                v2,
+                td1,
+                tau1,
+                td2,
+                tau2,
+            } => {
+                let (v1, v2) = (*v1 as f64, *v2 as f64);
+                let mut v = v1;
+                if t >= *td1 {
+                    v += (v2 - v1) * (1.0 - (-(t - td1) / tau1).exp());
+                }
+                if t >= *td2 {
+                    v += (v1 - v2) * (1.0 - (-(t - td2) / tau2).exp());
+                }
+                v as f32
+            }
+            Waveform::Pwl(points) => match points.iter().position(|(tp, _)| t < *tp) {
+                None => points.last().map_or(0.0, |(_, v)| *v),
+                Some(0) => points[0].1,
+                Some(i) => {
+                    let (t0, v0) = points[i - 1];
+                    let (t1, v1) = points[i];
+                    let ratio = (t - t0) / (t1 - t0);
+                    v0 + (v1 - v0) * ratio as f32
+                }
+            },
+            Waveform::Sffm {
+                vo,
+                va,
+                fc,
+                mdi,
+                fs,
+            } => {
+                let v = (2.0 * PI * fc * t + mdi * (2.0 * PI * fs * t).sin()).sin();
+                vo + va * v as f32
+            }
+        }
+    }
+
+    // 波形の基準の値. DC はその値, PULSE / EXP / PWL は初期値, SIN / SFFM はオフセット.
+    pub fn level(&self) -> f32 {
+        match self {
+            Waveform::Dc(v) => *v,
+            Waveform::Pulse { v1, .. } | Waveform::Exp { v1, .. } => *v1,
+            Waveform::Sin { vo, .. } | Waveform::Sffm { vo, .. } => *vo,
+            Waveform::Pwl(points) => points.first().map_or(0.0, |(_, v)| *v),
+        }
+    }
+
+    // 波形の値が取りうる範囲の幅
+    pub fn swing(&self) -> f32 {
+        match self {
+            Waveform::Dc(_) => 0.0,
+            Waveform::Pulse { v1, v2, .. } | Waveform::Exp { v1, v2, .. } => (v2 - v1).abs(),
+            Waveform::Sin { va, .. } | Waveform::Sffm { va, .. } => 2.0 * va.abs(),
+            Waveform::Pwl(points) => {
+                let values = points.iter().map(|(_, v)| *v);
+                let max = values.clone().fold(f32::NEG_INFINITY, f32::max);
+                let min = values.fold(f32::INFINITY, f32::min);
+                (max - min).max(0.0)
+            }
+        }
+    }
+
+    // from < t <= to の範囲で、波形の傾きが不連続に変わる時刻（ブレークポイント）
+    pub fn breakpoints(&self, from: f64, to: f64) -> Vec<f64> {
+        let points = match self {
+            Waveform::Dc(_) | Waveform::Sffm { .. } => vec![],
+            Waveform::Pulse {
+                td,
+                tr,
+                tf,
+                pw,
+                per,
+                ..
+            } => {
+                let edges = [0.0, *tr, tr + pw, tr + pw + tf];
+                if *per > 0.0 {
+                    // from を含む周期から to を超えるまで
+                    let first = ((from - td) / per).floor().max(0.0) as u64;
+                    let mut points = vec![];
+                    for k in first.. {
+                        let start = td + k as f64 * per;
+                        if start > to {
+                            break;
+                        }
+                        points.extend(edges.iter().map(|edge| start + edge));
+                    }
+                    points
+                } else {
+                    edges.iter().map(|edge| td + edge).collect()
+                }
+            }
+            Waveform::Sin { td, .. } => vec![*td],
+            Waveform::Exp { td1, td2, .. } => vec![*td1, *td2],
+            Waveform::Pwl(points) => points.iter().map(|(t, _)| *t).collect(),
+        };
+        points
+            .into_iter()
+            .filter(|t| from < *t && *t <= to)
+            .collect()
+    }
+}
+
+// 独立電源に共通する出力の状態
+#[derive(Debug)]
+pub struct Source {
+    waveform: Waveform,
+    // 波形全体に加える値. 基準の値をパラメータとして変更しても波形は変わらない.
+    offset: f32,
+    // 小信号解析用の振幅と位相 [度]
+    ac: (f32, f32),
+    // 波形を評価する時刻 [クロック数]. シミュレータの cycle に合わせる.
+    cycle: Cell<u64>,
+    // クロックの間にあるブレークポイントの時刻 [s]. 次のクロックで解除される.
+    seek: Cell<Option<f64>>,
+    // 波形が変更されたが、まだ再計算されていない.
+    changed: Cell<bool>,
+    // 最後に方程式に使った出力
+    stamped: Cell<f32>,
+}
+
+impl Source {
+    pub fn new(value: f32) -> Source {
+        Source {
+            waveform: Waveform::Dc(value),
+            offset: 0.0,
+            ac: (0.0, 0.0),
+            cycle: Cell::new(0),
+            seek: Cell::new(None),
+            changed: Cell::new(false),
+            stamped: Cell::new(value),
+        }
+    }
+
+    pub fn time(&self) -> f64 {
+        self.seek
+            .get()
+            .unwrap_or(self.cycle.get() as f64 / CLOCK_FREQUENCY)
+    }
+
+    // 現在の時刻における出力
+    pub fn value(&self) -> f32 {
+        self.waveform.value(self.time()) + self.offset
+    }
+
+    // 方程式に使う出力. clk ではこの値からの変化を調べる.
+    pub fn stamp_value(&self) -> f32 {
+        let value = self.value();
+        self.stamped.set(value);
+        value
+    }
+
+    pub fn waveform(&self) -> &Waveform {
+        &self.waveform
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+        self.offset = 0.0;
+        self.changed.set(true);
+    }
+
+    // 波形の基準の値 (voltage / current パラメータ)
+    pub fn level(&self) -> f32 {
+        self.waveform.level() + self.offset
+    }
+
+    // 基準の値を変更する. 波形は変えずに全体をずらす.
+    pub fn set_level(&mut self, value: f32) {
+        self.offset = value - self.waveform.level();
+        self.changed.set(true);
+    }
+
+    pub fn ac(&self) -> (f32, f32) {
+        self.ac
+    }
+
+    // 小信号解析用の振幅と位相を複素数にしたもの
+    pub fn ac_phasor(&self) -> Complex<f64> {
+        phasor(self.ac.0, self.ac.1)
+    }
+
+    pub fn set_ac(&mut self, magnitude: f32, phase: f32) {
+        self.ac = (magnitude, phase);
+    }
+
+    // 素子を追加した時点のシミュレータの時刻に合わせる
+    pub fn set_cycle(&mut self, cycle: u64) {
+        self.cycle.set(cycle);
+        self.seek.set(None);
+    }
+
+    // 現在のクロックと次のクロックの間の時刻 time [s] で出力を評価する
+    pub fn seek(&self, time: f64) {
+        self.seek.set(Some(time));
+    }
+
+    // クロックを進め、出力が変化した場合は true を返す.
+    //   ・変化が分解能より小さい場合は、波形の角（ブレークポイント）を通過した時だけ true.
+    pub fn clk(&self) -> bool {
+        let changed = self.changed.replace(false);
+        let from = self.time();
+        self.seek.set(None);
+        self.cycle.set(self.cycle.get() + 1);
+        let to = self.time();
+
+        let diff = (self.value() - self.stamped.get()).abs();
+        if diff == 0.0 {
+            return changed;
+        }
+        changed
+            || diff >= self.waveform.swing() * RESOLUTION
+            || !self.waveform.breakpoints(from, to).is_empty()
+    }
+
+    pub fn breakpoints(&self, from: f64, to: f64) -> Vec<f64> {
+        self.waveform.breakpoints(from, to)
+    }
+}
+
+impl Simulator {
+    fn with_source<T, F: FnOnce(&mut Source) -> T>(&mut self, element_id: usize, f: F) -> T {
+        let mut element = self.elements.get(&element_id).unwrap().borrow_mut();
+        let any = element.as_any();
+        if any.is::<IndVoltageSrc>() {
+            f(&mut any.downcast_mut::<IndVoltageSrc>().unwrap().source)
+        } else if any.is::<IndCurrentSrc>() {
+            f(&mut any.downcast_mut::<IndCurrentSrc>().unwrap().source)
+        } else {
+            panic!("is not independent source");
+        }
+    }
+
+    // 独立電源の出力波形
+    pub fn src_waveform(&mut self, element_id: usize) -> Waveform {
+        self.with_source(element_id, |source| source.waveform().clone())
+    }
+
+    // 独立電源の出力波形を変更する
+    pub fn src_set_waveform(&mut self, element_id: usize, waveform: Waveform) {
+        self.with_source(element_id, |source| source.set_waveform(waveform));
+    }
+
+    // 独立電源の小信号解析用の振幅と位相 [度] を設定する
+    pub fn src_set_ac(&mut self, element_id: usize, magnitude: f32, phase: f32) {
+        self.with_source(element_id, |source| source.set_ac(magnitude, phase));
+    }
+}
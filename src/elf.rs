@@ -0,0 +1,137 @@
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::Elf;
+use serde::*;
+use std::fmt::Write;
+
+// AVR の ELF ではメモリ空間ごとにアドレスがずらされている.
+//   ・Flash: 0x000000-, SRAM: 0x800000-, EEPROM: 0x810000-
+const DATA_OFFSET: u64 = 0x800000;
+const EEPROM_SECTION: &str = ".eeprom";
+
+// avr-gcc / arduino-cli が出力した ELF から取り出したプログラム
+#[derive(Debug)]
+pub struct ElfProgram {
+    // Flash に書き込むイメージ (Intel HEX)
+    pub hex: String,
+    // EEPROM の初期値
+    pub eeprom: Vec<u8>,
+    pub symbols: Symbols,
+}
+
+// シンボル. address は Flash 上のバイトアドレス.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+    pub is_function: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Symbols(pub Vec<Symbol>);
+
+impl Symbols {
+    // アドレスを含む関数名を "main+0x1a" の形式で返す.
+    //   ・PC はワードアドレスなので、2 倍してから渡すこと.
+    pub fn symbolize(&self, address: u32) -> Option<String> {
+        self.0
+            .iter()
+            .filter(|sym| sym.is_function)
+            .filter(|sym| sym.address <= address && address < sym.address + sym.size.max(1))
+            .max_by_key(|sym| sym.address)
+            .map(|sym| match address - sym.address {
+                0 => sym.name.clone(),
+                offset => format!("{}+{:#x}", sym.name, offset),
+            })
+    }
+}
+
+pub fn load_elf(bytes: &[u8]) -> Result<ElfProgram, String> {
+    let elf = Elf::parse(bytes).map_err(|err| format!("invalid ELF: {}", err))?;
+
+    // Flash: ロードされるセグメントを物理アドレス (LMA) に配置する.
+    //   ・.data の初期値も LMA は Flash 上にある.
+    let mut flash: Vec<u8> = vec![];
+    for ph in elf.program_headers.iter() {
+        if ph.p_type != PT_LOAD || ph.p_filesz == 0 || ph.p_paddr >= DATA_OFFSET {
+            continue;
+        }
+        let data = segment(bytes, ph.p_offset, ph.p_filesz)?;
+        let start = ph.p_paddr as usize;
+        if flash.len() < start + data.len() {
+            flash.resize(start + data.len(), 0xff);
+        }
+        flash[start..start + data.len()].copy_from_slice(data);
+    }
+    if flash.is_empty() {
+        return Err("no loadable flash segment".to_string());
+    }
+
+    // EEPROM: .eeprom セクション
+    let mut eeprom = vec![];
+    for sh in elf.section_headers.iter() {
+        if let Some(Ok(EEPROM_SECTION)) = elf.shdr_strtab.get(sh.sh_name) {
+            eeprom = segment(bytes, sh.sh_offset, sh.sh_size)?.to_vec();
+        }
+    }
+
+    // シンボル表. Flash 上のシンボルだけを保持する.
+    let mut symbols = vec![];
+    for sym in elf.syms.iter() {
+        if sym.st_value >= DATA_OFFSET {
+            continue;
+        }
+        match elf.strtab.get(sym.st_name) {
+            Some(Ok(name)) if !name.is_empty() => symbols.push(Symbol {
+                name: name.to_string(),
+                address: sym.st_value as u32,
+                size: sym.st_size as u32,
+                is_function: sym.is_function(),
+            }),
+            _ => (),
+        }
+    }
+    symbols.sort_by_key(|sym| sym.address);
+
+    Ok(ElfProgram {
+        hex: to_intel_hex(&flash),
+        eeprom,
+        symbols: Symbols(symbols),
+    })
+}
+
+fn segment(bytes: &[u8], offset: u64, size: u64) -> Result<&[u8], String> {
+    bytes
+        .get(offset as usize..(offset + size) as usize)
+        .ok_or("segment out of range".to_string())
+}
+
+// バイナリイメージを Intel HEX に変換する
+pub fn to_intel_hex(image: &[u8]) -> String {
+    let mut hex = String::new();
+    let mut upper = 0;
+    for (i, chunk) in image.chunks(16).enumerate() {
+        let address = i * 16;
+        // 64KB を超える場合は拡張リニアアドレスレコードを挟む
+        if address >> 16 != upper {
+            upper = address >> 16;
+            hex_record(&mut hex, 0, 0x04, &[(upper >> 8) as u8, upper as u8]);
+        }
+        hex_record(&mut hex, address as u16, 0x00, chunk);
+    }
+    hex_record(&mut hex, 0, 0x01, &[]);
+    hex
+}
+
+fn hex_record(hex: &mut String, address: u16, record_type: u8, data: &[u8]) {
+    let mut sum = data.len() as u8;
+    sum = sum.wrapping_add((address >> 8) as u8);
+    sum = sum.wrapping_add(address as u8);
+    sum = sum.wrapping_add(record_type);
+    write!(hex, ":{:02X}{:04X}{:02X}", data.len(), address, record_type).unwrap();
+    for byte in data {
+        sum = sum.wrapping_add(*byte);
+        write!(hex, "{:02X}", byte).unwrap();
+    }
+    writeln!(hex, "{:02X}", sum.wrapping_neg()).unwrap();
+}
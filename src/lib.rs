@@ -1,3 +1,12 @@
+pub mod ac;
+pub mod average;
 pub mod elements;
+pub mod elf;
+pub mod model;
+pub mod monte_carlo;
+pub mod operating_point;
+pub mod sensitivity;
 pub mod simulator;
+pub mod subcircuit;
+pub mod sweep;
 pub mod wasm;
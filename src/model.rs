@@ -0,0 +1,271 @@
+use super::elements::bjt::{BjtModel, Polarity};
+use super::elements::diode::DiodeModel;
+use super::elements::led::LedColor;
+use super::elements::mosfet::{Channel, MosfetLevel, MosfetModel};
+use super::simulator::*;
+use std::collections::BTreeMap;
+
+// 組み込みの部品カタログ. 値は各メーカーの SPICE モデルを元に丸めたもの.
+//   ・LED は SPICE にない独自の型で、順方向電圧 VF, 直列抵抗 RS, 定格電流 IMAX を持つ.
+const CATALOG: &str = "
+* ダイオード
+.model 1N4148 D(IS=2.52n N=1.752 RS=0.568 BV=100 IBV=100u)
+.model 1N4007 D(IS=7.03n N=1.808 RS=0.0342 BV=1000 IBV=5u)
+.model 1N5819 D(IS=31.7u N=1.373 RS=0.051 BV=40 IBV=1m)
+.model 1N4733A D(IS=1.2n N=1.1 RS=7 BV=5.1 IBV=1m)
+* LED (5mm)
+.model LED_RED LED(VF=1.8 RS=10 IMAX=20m)
+.model LED_GREEN LED(VF=2.8 RS=12 IMAX=20m)
+.model LED_BLUE LED(VF=2.9 RS=12 IMAX=20m)
+.model LED_WHITE LED(VF=2.9 RS=12 IMAX=20m)
+* バイポーラトランジスタ
+.model 2N2222 NPN(IS=14.34f BF=255.9 BR=6.092 VAF=74.03 RB=10 RC=1)
+.model 2N3904 NPN(IS=6.734f BF=416.4 BR=0.7371 VAF=74.03 RB=10 RC=1)
+.model 2N3906 PNP(IS=1.41f BF=180.7 BR=4.977 VAF=18.7 RB=10 RC=2.5)
+.model BC547 NPN(IS=7.05f BF=378.6 BR=1 VAF=62.8 RB=10 RC=1)
+* MOSFET
+.model BS170 NMOS(LEVEL=1 VTO=1.824 KP=0.1233 LAMBDA=0.01)
+.model 2N7000 NMOS(LEVEL=1 VTO=2.0 KP=0.1 LAMBDA=0.01)
+.model BS250 PMOS(LEVEL=1 VTO=-2.1 KP=0.08 LAMBDA=0.01)
+";
+
+// 部品のモデル
+#[derive(Debug, Clone, PartialEq)]
+pub enum Model {
+    Diode(DiodeModel),
+    Led { vf: f32, rs: f32, i_max: f32 },
+    Bjt(Polarity, BjtModel),
+    Mosfet(Channel, MosfetModel),
+}
+
+// 名前付きのモデルの一覧. 名前は大文字小文字を区別しない.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelLibrary(BTreeMap<String, Model>);
+
+impl ModelLibrary {
+    // 組み込みのカタログを読み込んだもの
+    pub fn new() -> ModelLibrary {
+        let mut library = ModelLibrary(BTreeMap::new());
+        library.load(CATALOG).unwrap();
+        library
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Model> {
+        self.0.get(&name.to_uppercase())
+    }
+
+    pub fn insert(&mut self, name: &str, model: Model) {
+        self.0.insert(name.to_uppercase(), model);
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.0.keys().cloned().collect()
+    }
+
+    // SPICE の .model 文を読み込み、読み込んだモデルの名前を返す.
+    //   ・'+' で始まる行は前の行の続き、'*' で始まる行と ';' 以降はコメント.
+    //   ・.model 以外の文は無視する.
+    pub fn load(&mut self, text: &str) -> Result<Vec<String>, String> {
+        let mut statements: Vec<String> = vec![];
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() || line.starts_with('*') {
+                continue;
+            }
+            match (line.starts_with('+'), statements.last_mut()) {
+                (true, Some(last)) => {
+                    last.push(' ');
+                    last.push_str(&line[1..]);
+                }
+                _ => statements.push(line.to_string()),
+            }
+        }
+
+        let mut names = vec![];
+        for statement in statements.iter() {
+            if !statement.to_lowercase().starts_with(".model") {
+                continue;
+            }
+            let (name, model) = parse_model(statement)?;
+            self.insert(&name, model);
+            names.push(name.to_uppercase());
+        }
+        Ok(names)
+    }
+}
+
+impl Default for ModelLibrary {
+    fn default() -> ModelLibrary {
+        ModelLibrary::new()
+    }
+}
+
+// .model NAME TYPE(PARAM=VALUE ...)
+fn parse_model(statement: &str) -> Result<(String, Model), String> {
+    let text = statement.replace(['(', ')', ','], " ").replace('=', " = ");
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    if tokens.len() < 3 {
+        return Err(format!("invalid .model: {}", statement));
+    }
+    let name = tokens[1].to_string();
+
+    // 数値でないパラメータ (mfg=... など) やフラグは無視する.
+    let mut params = BTreeMap::new();
+    let mut i = 3;
+    while i < tokens.len() {
+        if tokens.get(i + 1) == Some(&"=") && i + 2 < tokens.len() {
+            if let Some(value) = parse_number(tokens[i + 2]) {
+                params.insert(tokens[i].to_uppercase(), value);
+            }
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let get = |key: &str, default: f32| params.get(key).cloned().unwrap_or(default);
+
+    let model = match tokens[2].to_uppercase().as_str() {
+        "D" => {
+            let d = DiodeModel::default();
+            Model::Diode(DiodeModel {
+                is: get("IS", d.is),
+                n: get("N", d.n),
+                rs: get("RS", d.rs),
+                bv: get("BV", d.bv),
+                ibv: get("IBV", d.ibv),
+            })
+        }
+        "LED" => Model::Led {
+            vf: get("VF", 1.8),
+            rs: get("RS", 10.0),
+            i_max: get("IMAX", 0.02),
+        },
+        kind @ "NPN" | kind @ "PNP" => {
+            let d = BjtModel::default();
+            let polarity = if kind == "NPN" {
+                Polarity::Npn
+            } else {
+                Polarity::Pnp
+            };
+            Model::Bjt(
+                polarity,
+                BjtModel {
+                    is: get("IS", d.is),
+                    bf: get("BF", d.bf),
+                    br: get("BR", d.br),
+                    vaf: get("VAF", get("VA", d.vaf)),
+                    rb: get("RB", d.rb),
+                    rc: get("RC", d.rc),
+                    re: get("RE", d.re),
+                },
+            )
+        }
+        kind @ "NMOS" | kind @ "PMOS" => {
+            let d = MosfetModel::default();
+            let channel = if kind == "NMOS" {
+                Channel::N
+            } else {
+                Channel::P
+            };
+            // EKV は LEVEL=44
+            let level = if get("LEVEL", 1.0) == 44.0 {
+                MosfetLevel::Ekv
+            } else {
+                MosfetLevel::Level1
+            };
+            Model::Mosfet(
+                channel,
+                MosfetModel {
+                    level,
+                    vto: get("VTO", d.vto).abs(),
+                    kp: get("KP", d.kp),
+                    w: get("W", d.w),
+                    l: get("L", d.l),
+                    lambda: get("LAMBDA", d.lambda),
+                    gamma: get("GAMMA", d.gamma),
+                    phi: get("PHI", d.phi),
+                    n: get("N", d.n),
+                },
+            )
+        }
+        kind => return Err(format!("{}: unsupported model type: {}", name, kind)),
+    };
+    Ok((name, model))
+}
+
+// SPICE の数値. 2.52n, 1meg, 10V のような接尾辞を解釈する.
+pub fn parse_number(token: &str) -> Option<f32> {
+    let token = token.to_lowercase();
+    let end = (1..=token.len())
+        .rev()
+        .find(|end| token.is_char_boundary(*end) && token[..*end].parse::<f64>().is_ok())?;
+    let value: f64 = token[..end].parse().unwrap();
+    let suffix = &token[end..];
+    let scale = if suffix.starts_with("meg") {
+        1e6
+    } else if suffix.starts_with("mil") {
+        25.4e-6
+    } else {
+        match suffix.chars().next() {
+            Some('t') => 1e12,
+            Some('g') => 1e9,
+            Some('k') => 1e3,
+            Some('m') => 1e-3,
+            Some('u') => 1e-6,
+            Some('n') => 1e-9,
+            Some('p') => 1e-12,
+            Some('f') => 1e-15,
+            _ => 1.0,
+        }
+    };
+    Some((value * scale) as f32)
+}
+
+impl Simulator {
+    // SPICE の .model 文を読み込み、読み込んだモデルの名前を返す.
+    pub fn load_models(&mut self, text: &str) -> Result<Vec<String>, String> {
+        self.models.load(text)
+    }
+
+    pub fn define_model(&mut self, name: &str, model: Model) {
+        self.models.insert(name, model);
+    }
+
+    // モデル名を指定して部品を作成する
+    pub fn add_part(&mut self, name: &str) -> Result<ElementId, String> {
+        let model = self
+            .models
+            .get(name)
+            .cloned()
+            .ok_or(format!("unknown model: {}", name))?;
+        let element_id = match model {
+            Model::Diode(model) => {
+                let id = self.add_diode();
+                self.diode_set_model(id, model);
+                id
+            }
+            Model::Led { vf, rs, i_max } => {
+                let id = self.add_led(LedColor::Red);
+                self.led_set_params(id, vf, rs, i_max);
+                id
+            }
+            Model::Bjt(polarity, model) => {
+                let id = match polarity {
+                    Polarity::Npn => self.add_npn(),
+                    Polarity::Pnp => self.add_pnp(),
+                };
+                self.bjt_set_model(id, model);
+                id
+            }
+            Model::Mosfet(channel, model) => {
+                let id = match channel {
+                    Channel::N => self.add_nmos(),
+                    Channel::P => self.add_pmos(),
+                };
+                self.mosfet_set_model(id, model);
+                id
+            }
+        };
+        Ok(element_id)
+    }
+}
@@ -0,0 +1,297 @@
+use super::simulator::*;
+use super::sweep::{Analysis, AnalysisResult};
+use serde::*;
+use wasm_bindgen::prelude::*;
+
+// 公差の分布
+//   ・Gaussian は公差を 3σ とする正規分布. 3σ を超えた値は公差の範囲に丸める.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Distribution {
+    Uniform,
+    Gaussian,
+}
+
+// 回路素子のパラメータの公差
+//   ・relative は公称値に対する割合 (±5% なら 0.05). 値の符号が変わらないよう 1 未満とする.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Tolerance {
+    pub relative: f32,
+    pub distribution: Distribution,
+}
+
+// 解析結果から取り出す値
+//   ・JSON では {"type": "voltage", "node_id": 1}, {"type": "current", "element_id": 2, "pin_id": 0}.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Probe {
+    // ノードの電圧 [V]
+    Voltage {
+        node_id: NodeId,
+    },
+    // 端子から回路素子に流れ込む電流 [A]
+    Current {
+        element_id: ElementId,
+        pin_id: PinId,
+    },
+}
+
+// 再現性のある乱数 (xorshift64*)
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // seed = 0 でも状態が 0 にならないように混ぜる (splitmix64)
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng((z ^ (z >> 31)) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // [0, 1) の一様乱数
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // 標準正規分布の乱数 (Box-Muller 法)
+    pub fn gaussian(&mut self) -> f64 {
+        let u1 = 1.0 - self.uniform();
+        let u2 = self.uniform();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+
+    // 公差に従って公称値をばらつかせる
+    pub fn vary(&mut self, nominal: f32, tolerance: &Tolerance) -> f32 {
+        let deviation = match tolerance.distribution {
+            Distribution::Uniform => 2.0 * self.uniform() - 1.0,
+            Distribution::Gaussian => (self.gaussian() / 3.0).clamp(-1.0, 1.0),
+        };
+        nominal * (1.0 + tolerance.relative * deviation as f32)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ParamValue {
+    pub element_id: ElementId,
+    pub name: String,
+    pub value: f32,
+}
+
+// 1 回分の結果. outputs は probes と同じ順に並ぶ.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct MonteCarloRun {
+    pub params: Vec<ParamValue>,
+    pub outputs: Vec<f32>,
+    pub result: AnalysisResult,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Statistics {
+    pub probe: Probe,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    // 標本標準偏差
+    pub sigma: f32,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct MonteCarlo {
+    // 公称値での outputs
+    pub nominal: Vec<f32>,
+    pub runs: Vec<MonteCarloRun>,
+    pub statistics: Vec<Statistics>,
+}
+
+impl Statistics {
+    fn new(probe: Probe, values: &[f32]) -> Statistics {
+        let n = values.len() as f64;
+        let mean = values.iter().map(|v| *v as f64).sum::<f64>() / n;
+        let var = values
+            .iter()
+            .map(|v| (*v as f64 - mean).powi(2))
+            .sum::<f64>()
+            / (n - 1.0).max(1.0);
+        Statistics {
+            probe,
+            min: values.iter().cloned().fold(f32::INFINITY, f32::min),
+            max: values.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
+            mean: mean as f32,
+            sigma: var.sqrt() as f32,
+        }
+    }
+}
+
+// 公差を設定したパラメータの公称値と範囲
+struct Varied {
+    element_id: ElementId,
+    name: String,
+    nominal: f32,
+    tolerance: Tolerance,
+    min: f32,
+    max: f32,
+}
+
+impl Simulator {
+    // 回路素子のパラメータに公差を設定する
+    pub fn set_tolerance(
+        &mut self,
+        element_id: ElementId,
+        name: &str,
+        tolerance: Tolerance,
+    ) -> Result<(), String> {
+        if self.param_info(element_id, name)?.discrete {
+            return Err(format!("{} cannot have a tolerance", name));
+        }
+        if !(0.0 <= tolerance.relative && tolerance.relative < 1.0) {
+            return Err(format!("invalid tolerance: {}", tolerance.relative));
+        }
+        self.tolerances
+            .insert((element_id, name.to_string()), tolerance);
+        Ok(())
+    }
+
+    pub fn clear_tolerance(&mut self, element_id: ElementId, name: &str) {
+        self.tolerances.remove(&(element_id, name.to_string()));
+    }
+
+    // 最後に解いた方程式から値を取り出す
+    pub fn probe(&self, probe: &Probe) -> Result<f32, String> {
+        let eq = self
+            .equation
+            .as_ref()
+            .ok_or("no state calculated".to_string())?;
+        self.probe_equation(probe, eq)
+    }
+
+    pub fn probe_equation(&self, probe: &Probe, eq: &Equation) -> Result<f32, String> {
+        match probe {
+            Probe::Voltage { node_id } => {
+                if !self.nodes.contains(node_id) {
+                    return Err(format!("unknown node: {}", node_id));
+                }
+                Ok(eq.voltage(*node_id))
+            }
+            Probe::Current { element_id, pin_id } => {
+                let element = self
+                    .elements
+                    .get(element_id)
+                    .ok_or(format!("unknown element: {}", element_id))?;
+                let currents = element.borrow().currents(eq);
+                currents
+                    .get(*pin_id)
+                    .cloned()
+                    .ok_or(format!("unknown pin: {}", pin_id))
+            }
+        }
+    }
+
+    // モンテカルロ解析
+    //   ・公差を設定したパラメータをばらつかせて runs 回の解析を行い、probes の値の統計を求める.
+    //   ・過渡解析は毎回同じ時刻・同じ内部状態から始め、終了時の値を使う.
+    //     内部状態を戻せない MCU を含む回路ではエラーになる.
+    //   ・ばらつかせた値がパラメータの範囲を超える場合は範囲の端に丸める.
+    //   ・同じ seed なら同じ結果になる. 途中でエラーになっても、終了後はパラメータと時刻を元に戻す.
+    pub fn monte_carlo(
+        &mut self,
+        runs: usize,
+        seed: u64,
+        analysis: &Analysis,
+        probes: &[Probe],
+    ) -> Result<MonteCarlo, String> {
+        let varied: Vec<Varied> = self
+            .tolerances
+            .iter()
+            .map(|((element_id, name), tolerance)| {
+                let info = self.param_info(*element_id, name)?;
+                Ok(Varied {
+                    element_id: *element_id,
+                    name: name.clone(),
+                    nominal: self.get_param(*element_id, name)?,
+                    tolerance: *tolerance,
+                    min: info.min,
+                    max: info.max,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let start = self.begin_analysis(analysis)?;
+
+        let result = self.monte_carlo_runs(runs, seed, analysis, probes, &varied, start);
+
+        let mut restored = Ok(());
+        for v in varied.iter() {
+            restored = restored.and(self.set_param(v.element_id, &v.name, v.nominal));
+        }
+        self.rewind(start);
+        let updated = self.update_state();
+        result.and_then(|result| restored.and(updated).map(|_| result))
+    }
+
+    fn monte_carlo_runs(
+        &mut self,
+        runs: usize,
+        seed: u64,
+        analysis: &Analysis,
+        probes: &[Probe],
+        varied: &[Varied],
+        start: u64,
+    ) -> Result<MonteCarlo, String> {
+        let outputs = |sim: &Simulator| -> Result<Vec<f32>, String> {
+            probes.iter().map(|probe| sim.probe(probe)).collect()
+        };
+
+        self.analyze(analysis, start)?;
+        let nominal = outputs(self)?;
+
+        let mut rng = Rng::new(seed);
+        let mut results = vec![];
+        for _ in 0..runs {
+            let mut params = vec![];
+            for v in varied.iter() {
+                let value = rng.vary(v.nominal, &v.tolerance).clamp(v.min, v.max);
+                self.set_param(v.element_id, &v.name, value)?;
+                params.push(ParamValue {
+                    element_id: v.element_id,
+                    name: v.name.clone(),
+                    value,
+                });
+            }
+            let result = self.analyze(analysis, start)?;
+            results.push(MonteCarloRun {
+                params,
+                outputs: outputs(self)?,
+                result,
+            });
+        }
+
+        let statistics = if results.is_empty() {
+            vec![]
+        } else {
+            probes
+                .iter()
+                .enumerate()
+                .map(|(i, probe)| {
+                    let values: Vec<f32> = results.iter().map(|run| run.outputs[i]).collect();
+                    Statistics::new(*probe, &values)
+                })
+                .collect()
+        };
+
+        Ok(MonteCarlo {
+            nominal,
+            runs: results,
+            statistics,
+        })
+    }
+}
@@ -0,0 +1,141 @@
+use super::simulator::*;
+use serde::*;
+use std::fmt;
+
+// 動作点 (SPICE の .OP に相当する)
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct OperatingPoint {
+    pub time: f64,
+    pub nodes: Vec<NodeOp>,
+    pub elements: Vec<ElementOp>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct NodeOp {
+    pub id: NodeId,
+    pub name: Option<String>,
+    pub voltage: f32,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ElementOp {
+    pub id: ElementId,
+    pub name: Option<String>,
+    pub kind: &'static str,
+    pub pins: Vec<PinOp>,
+    // 消費電力 [W]. 電源など電力を供給している場合は負.
+    pub power: f32,
+    // 非線形素子の動作領域. 線形素子は None.
+    pub region: Option<&'static str>,
+    // 小信号パラメータ (gm など)
+    pub small_signal: ElementState,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct PinOp {
+    pub name: &'static str,
+    pub node_id: NodeId,
+    pub voltage: f32,
+    // 端子から素子に流れ込む電流 [A]
+    pub current: f32,
+}
+
+impl Simulator {
+    // 最後に求めた状態での動作点
+    pub fn operating_point(&self) -> Result<OperatingPoint, String> {
+        let eq = self
+            .equation
+            .as_ref()
+            .ok_or("no state calculated".to_string())?;
+
+        let nodes = self
+            .nodes
+            .iter()
+            .filter(|node_id| **node_id != 0)
+            .map(|node_id| NodeOp {
+                id: *node_id,
+                name: self.node_names.get(node_id).cloned(),
+                voltage: eq.voltage(*node_id),
+            })
+            .collect();
+
+        let mut elements = vec![];
+        for (element_id, element) in self.elements.iter() {
+            let element = element.borrow();
+            let currents = element.currents(eq);
+            let pins: Vec<PinOp> = element
+                .pin_names()
+                .into_iter()
+                .zip(element.pin_nodes())
+                .enumerate()
+                .map(|(pin_id, (name, node_id))| PinOp {
+                    name,
+                    node_id,
+                    voltage: eq.voltage(node_id),
+                    current: currents.get(pin_id).cloned().unwrap_or(0.0),
+                })
+                .collect();
+            let power = pins.iter().map(|pin| pin.voltage * pin.current).sum();
+            let (region, small_signal) = match element.operating_point(eq) {
+                Some((region, params)) => (Some(region), params),
+                None => (None, ElementState::new()),
+            };
+            elements.push(ElementOp {
+                id: *element_id,
+                name: self.element_names.get(element_id).cloned(),
+                kind: element.kind(),
+                pins,
+                power,
+                region,
+                small_signal,
+            });
+        }
+
+        Ok(OperatingPoint {
+            time: self.time(),
+            nodes,
+            elements,
+        })
+    }
+}
+
+// 表形式のテキスト
+impl fmt::Display for OperatingPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Operating point at t = {:e} s", self.time)?;
+        writeln!(f)?;
+        writeln!(f, "{:<16} {:>14}", "node", "voltage [V]")?;
+        for node in self.nodes.iter() {
+            let name = node.name.clone().unwrap_or(node.id.to_string());
+            writeln!(f, "{:<16} {:>14.6}", name, node.voltage)?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<16} {:<16} {:>14} {:<16}",
+            "element", "kind", "power [W]", "region"
+        )?;
+        for element in self.elements.iter() {
+            let name = element.name.clone().unwrap_or(element.id.to_string());
+            writeln!(
+                f,
+                "{:<16} {:<16} {:>14.6e} {:<16}",
+                name,
+                element.kind,
+                element.power,
+                element.region.unwrap_or("-")
+            )?;
+            for pin in element.pins.iter() {
+                writeln!(
+                    f,
+                    "    {:<12} node {:<6} V = {:<12.6} I = {:.6e}",
+                    pin.name, pin.node_id, pin.voltage, pin.current
+                )?;
+            }
+            for (key, value) in element.small_signal.iter() {
+                writeln!(f, "    {:<12} {:.6e}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
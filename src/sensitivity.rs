@@ -0,0 +1,288 @@
+use super::elements::element::*;
+use super::monte_carlo::{ParamValue, Probe};
+use super::simulator::*;
+use nalgebra::base::{DMatrix, DVector};
+use serde::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// パラメータを微小に変化させる割合. 値が 0 の場合はこの値を絶対値として使う.
+const PARAM_DELTA: f32 = 1e-3;
+// 電流の出力を x で数値微分するときの刻み (ノード・内部ノードの電圧 [V], src の電流 [A])
+const VOLTAGE_DELTA: f32 = 1e-3;
+const CURRENT_DELTA: f32 = 1e-6;
+
+// パラメータを変化させたときの方程式の残差と各出力の値.
+//   ・変化させると方程式の大きさが変わる場合は None.
+type Evaluation = Option<(DVector<f64>, Vec<f32>)>;
+
+// 1 つのパラメータに対する感度
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ParamSensitivity {
+    pub element_id: ElementId,
+    pub name: String,
+    pub value: f32,
+    // d(出力) / d(パラメータ)
+    pub derivative: f32,
+    // パラメータが 1% 変化したときの出力の変化 (derivative * value / 100)
+    pub per_percent: f32,
+}
+
+// 出力の値と、全てのパラメータに対する感度
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Sensitivity {
+    pub probe: Probe,
+    pub value: f32,
+    pub params: Vec<ParamSensitivity>,
+}
+
+// 公差の範囲で出力が最小・最大になるパラメータの組み合わせ
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Corner {
+    pub value: f32,
+    pub params: Vec<ParamValue>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct WorstCase {
+    pub probe: Probe,
+    pub nominal: f32,
+    pub min: Corner,
+    pub max: Corner,
+}
+
+// 回路素子が方程式に加える段の数 (src のピンと内部ノード)
+fn row_count(element: &Rc<RefCell<dyn Element>>) -> usize {
+    let element = element.borrow();
+    element
+        .output_pins()
+        .iter()
+        .filter(|is_output| **is_output)
+        .count()
+        + element.internal_nodes()
+}
+
+// x を与えた方程式. A, z はスタンプを押す前の状態.
+fn equation_at(eq: &Equation, x: DVector<f32>) -> Equation {
+    let dim = x.len();
+    Equation {
+        a: DMatrix::<f32>::zeros(dim, dim),
+        x,
+        z: DVector::<f32>::zeros(dim),
+        node_index: eq.node_index.clone(),
+        src_index: eq.src_index.clone(),
+        internal_index: eq.internal_index.clone(),
+    }
+}
+
+impl Simulator {
+    // x を固定したままスタンプを押し直した残差 A x - z
+    fn residual(&self, eq: &Equation) -> DVector<f64> {
+        let mut eq = equation_at(eq, eq.x.clone());
+        for element in self.elements.values() {
+            element.borrow().stamp(&mut eq);
+        }
+        let a = eq.a.map(|v| v as f64);
+        let x = eq.x.map(|v| v as f64);
+        let z = eq.z.map(|v| v as f64);
+        a * x - z
+    }
+
+    // 出力の x による微分
+    //   ・電圧は x の成分そのもの. 電流は回路素子の currents を数値微分する.
+    fn probe_gradient(&self, probe: &Probe, eq: &Equation) -> Result<DVector<f64>, String> {
+        let mut c = DVector::<f64>::zeros(eq.x.len());
+        match probe {
+            Probe::Voltage { node_id } => {
+                if let Some(index) = eq.node(*node_id) {
+                    c[index] = 1.0;
+                }
+            }
+            Probe::Current { .. } => {
+                for i in 0..eq.x.len() {
+                    let h = if eq.is_current(i) {
+                        CURRENT_DELTA
+                    } else {
+                        VOLTAGE_DELTA
+                    };
+                    let mut x = eq.x.clone();
+                    x[i] += h;
+                    let upper = self.probe_equation(probe, &equation_at(eq, x.clone()))?;
+                    x[i] -= 2.0 * h;
+                    let lower = self.probe_equation(probe, &equation_at(eq, x))?;
+                    c[i] = (upper as f64 - lower as f64) / (2.0 * h as f64);
+                }
+            }
+        }
+        Ok(c)
+    }
+
+    // 直流感度解析 (SPICE の .SENS に相当する)
+    //   ・最後に解いた方程式 A x = z について、随伴方程式 A^T λ = dy/dx を解き、
+    //     dy/dp = ∂y/∂p - λ^T ∂(Ax - z)/∂p として全てのパラメータに対する感度を求める.
+    //   ・∂(Ax - z)/∂p は x を固定したままパラメータを微小に変化させ、スタンプを押し直して求める.
+    //   ・方程式の次元が変わるパラメータ (BJT の直列抵抗が 0 の場合など) と、
+    //     離散的な値のパラメータ (スイッチの状態など) は除く.
+    pub fn sensitivity(&self, probes: &[Probe]) -> Result<Vec<Sensitivity>, String> {
+        let eq = self
+            .equation
+            .as_ref()
+            .ok_or("no state calculated".to_string())?;
+
+        let at = eq.a.map(|v| v as f64).transpose().lu();
+        let mut adjoints = vec![];
+        for probe in probes.iter() {
+            let c = self.probe_gradient(probe, eq)?;
+            let lambda = at.solve(&c).ok_or("singular matrix".to_string())?;
+            adjoints.push((self.probe_equation(probe, eq)?, lambda));
+        }
+        let mut results: Vec<Sensitivity> = probes
+            .iter()
+            .zip(adjoints.iter())
+            .map(|(probe, (value, _))| Sensitivity {
+                probe: *probe,
+                value: *value,
+                params: vec![],
+            })
+            .collect();
+
+        for (element_id, element) in self.elements.iter() {
+            let dim = row_count(element);
+            let params = element.borrow().params();
+            for info in params.into_iter().filter(|info| !info.discrete) {
+                let value = match element.borrow().get_param(info.name) {
+                    Some(value) => value,
+                    None => continue,
+                };
+
+                // 範囲内なら中心差分、端では片側差分にする
+                let h = if value != 0.0 {
+                    value.abs() * PARAM_DELTA
+                } else {
+                    PARAM_DELTA
+                };
+                let upper = if value + h <= info.max {
+                    value + h
+                } else {
+                    value
+                };
+                let lower = if value - h >= info.min {
+                    value - h
+                } else {
+                    value
+                };
+                if upper == lower {
+                    continue;
+                }
+
+                let evaluate = |p: f32| -> Result<Evaluation, String> {
+                    if element.borrow_mut().set_param(info.name, p).is_err()
+                        || row_count(element) != dim
+                    {
+                        return Ok(None);
+                    }
+                    let outputs = probes
+                        .iter()
+                        .map(|probe| self.probe_equation(probe, eq))
+                        .collect::<Result<Vec<f32>, String>>()?;
+                    Ok(Some((self.residual(eq), outputs)))
+                };
+                let upper_result = evaluate(upper);
+                let lower_result = evaluate(lower);
+                element.borrow_mut().set_param(info.name, value)?;
+                let (upper_result, lower_result) = match (upper_result?, lower_result?) {
+                    (Some(u), Some(l)) => (u, l),
+                    _ => continue,
+                };
+
+                let dp = (upper - lower) as f64;
+                let df = (&upper_result.0 - &lower_result.0) / dp;
+                for (i, (_, lambda)) in adjoints.iter().enumerate() {
+                    let dy = (upper_result.1[i] as f64 - lower_result.1[i] as f64) / dp;
+                    let derivative = (dy - lambda.dot(&df)) as f32;
+                    results[i].params.push(ParamSensitivity {
+                        element_id: *element_id,
+                        name: info.name.to_string(),
+                        value,
+                        derivative,
+                        per_percent: derivative * value / 100.0,
+                    });
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    // ワーストケース解析
+    //   ・公差を設定したパラメータを感度の符号に従って公差の端に振り、出力が最小・最大になる
+    //     組み合わせを求める. 各組み合わせで動作点を計算し直した値を返す.
+    //   ・正規分布の公差も 3σ を端とする.
+    //   ・公差の端がパラメータの範囲を超える場合は範囲の端に丸める.
+    //   ・途中でエラーになっても、終了後はパラメータを元に戻す.
+    pub fn worst_case(&mut self, probes: &[Probe]) -> Result<Vec<WorstCase>, String> {
+        let sensitivities = self.sensitivity(probes)?;
+        let tolerances: Vec<(ElementId, String, f32, f32)> = self
+            .tolerances
+            .iter()
+            .map(|((element_id, name), tolerance)| {
+                self.get_param(*element_id, name)
+                    .map(|nominal| (*element_id, name.clone(), nominal, tolerance.relative))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let result: Result<Vec<WorstCase>, String> = sensitivities
+            .iter()
+            .map(|sensitivity| {
+                Ok(WorstCase {
+                    probe: sensitivity.probe,
+                    nominal: sensitivity.value,
+                    min: self.corner(sensitivity, &tolerances, -1.0)?,
+                    max: self.corner(sensitivity, &tolerances, 1.0)?,
+                })
+            })
+            .collect();
+
+        let mut restored = Ok(());
+        for (element_id, name, nominal, _) in tolerances.iter() {
+            restored = restored.and(self.set_param(*element_id, name, *nominal));
+        }
+        let updated = self.update_state();
+        result.and_then(|result| restored.and(updated).map(|_| result))
+    }
+
+    // 出力を sign の向きに動かす公差の端で動作点を求める
+    fn corner(
+        &mut self,
+        sensitivity: &Sensitivity,
+        tolerances: &[(ElementId, String, f32, f32)],
+        sign: f32,
+    ) -> Result<Corner, String> {
+        let mut params = vec![];
+        for (element_id, name, nominal, relative) in tolerances.iter() {
+            let derivative = sensitivity
+                .params
+                .iter()
+                .find(|p| p.element_id == *element_id && p.name == *name)
+                .map_or(0.0, |p| p.derivative);
+            let delta = (nominal * relative).abs();
+            let value = if derivative * sign >= 0.0 {
+                nominal + delta
+            } else {
+                nominal - delta
+            };
+            let info = self.param_info(*element_id, name)?;
+            let value = value.clamp(info.min, info.max);
+            self.set_param(*element_id, name, value)?;
+            params.push(ParamValue {
+                element_id: *element_id,
+                name: name.clone(),
+                value,
+            });
+        }
+        self.update_state()?;
+        Ok(Corner {
+            value: self.probe(&sensitivity.probe)?,
+            params,
+        })
+    }
+}
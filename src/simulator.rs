@@ -1,4 +1,8 @@
+use super::average::*;
 use super::elements::element::*;
+use super::model::ModelLibrary;
+use super::monte_carlo::Tolerance;
+use super::subcircuit::Subcircuit;
 use nalgebra::base::{DMatrix, DVector};
 use serde::ser::SerializeMap;
 use serde::*;
@@ -10,6 +14,9 @@ use wasm_bindgen::prelude::*;
 const SOLVER_ACCURACY: f32 = 0.0001;
 const SOLVER_COUNT_MAX: u32 = 100;
 
+// MCU のクロック周波数 (ArduinoUno: 16MHz). シミュレーション時刻はクロック数から求める.
+pub const CLOCK_FREQUENCY: f64 = 16_000_000.0;
+
 pub type ElementId = usize;
 pub type PinId = usize;
 pub type NodeId = usize;
@@ -29,6 +36,27 @@ pub struct Simulator {
     //   ・MCU のクロックを連続で処理して変化がある場合だけ出力する、という
     //     仕組みを作るために、以前の state を内部的に保持する必要がある.
     pub state: Option<State>,
+
+    // シミュレーション時刻
+    //   ・MCU のクロックを進めた回数. 時刻は cycle / CLOCK_FREQUENCY [s].
+    pub cycle: u64,
+
+    // 最後に解いた方程式. 解けなかった場合は None.
+    pub equation: Option<Equation>,
+
+    // PWM などの出力を時間平均する. 無効の場合は None.
+    pub averager: Option<Averager>,
+
+    // サブサーキットの定義と、回路素子・ノードの名前 ("X1.R2" など)
+    pub subcircuits: BTreeMap<String, Subcircuit>,
+    pub element_names: BTreeMap<ElementId, String>,
+    pub node_names: BTreeMap<NodeId, String>,
+
+    // 部品のモデル. 組み込みのカタログに .model 文で追加できる.
+    pub models: ModelLibrary,
+
+    // 回路素子のパラメータの公差. モンテカルロ解析で使う.
+    pub tolerances: BTreeMap<(ElementId, String), Tolerance>,
 }
 
 impl Simulator {
@@ -38,12 +66,25 @@ impl Simulator {
 
         Simulator {
             elements: BTreeMap::new(),
-            nodes: nodes,
+            nodes,
             links: BTreeSet::new(),
             state: None,
+            cycle: 0,
+            equation: None,
+            averager: None,
+            subcircuits: BTreeMap::new(),
+            element_names: BTreeMap::new(),
+            node_names: BTreeMap::new(),
+            models: ModelLibrary::new(),
+            tolerances: BTreeMap::new(),
         }
     }
 
+    // シミュレーション時刻 [s]
+    pub fn time(&self) -> f64 {
+        self.cycle as f64 / CLOCK_FREQUENCY
+    }
+
     pub fn add_node(&mut self) -> NodeId {
         let id = self.nodes.iter().max().unwrap() + 1;
         self.nodes.insert(id);
@@ -82,7 +123,16 @@ impl Simulator {
                         state.insert(*node_id, eq.x[*index]);
                     }
                 }
-                Ok(State::new(state))
+                let mut elements = BTreeMap::new();
+                for (element_id, element) in self.elements.iter() {
+                    element.borrow().solved(&eq);
+                    let outputs = element.borrow().outputs(&eq);
+                    if !outputs.is_empty() {
+                        elements.insert(*element_id, outputs);
+                    }
+                }
+                self.equation = Some(eq);
+                Ok(State(state, elements))
             }
             Err(err) => {
                 // 失敗した場合は電源を落とした状態にしてみる..
@@ -93,6 +143,7 @@ impl Simulator {
                         state.insert(*node_id, 0.0);
                     }
                 }
+                self.equation = None;
                 Ok(State::new(state))
             }
         }
@@ -103,6 +154,10 @@ impl Simulator {
         match self.state() {
             Ok(state) => {
                 self.state = Some(state.clone());
+                if let Some(averager) = self.averager.as_mut() {
+                    averager.clear();
+                }
+                self.record_average(&state);
                 Ok(state)
             }
             Err(err) => Err(err),
@@ -118,10 +173,12 @@ impl Simulator {
         }
 
         // MCU のクロックを進める
+        self.cycle += 1;
+        //   ・MCU が複数ある場合も全てのクロックを進める（短絡評価しない）.
         let is_updated = self
             .elements
             .values()
-            .fold(false, |sum, element| sum || element.borrow().clk());
+            .fold(false, |sum, element| element.borrow().clk() || sum);
 
         // クロックを進めたものの回路の構成に変化がない（ IOPort などに変化がない）場合は終了
         if !is_updated {
@@ -131,6 +188,7 @@ impl Simulator {
         // 回路の構成が変わったので状態を再計算. 変化がある場合だけ値を返す.
         match self.state() {
             Ok(state) => {
+                self.record_average(&state);
                 if self.state.as_ref() == Some(&state) {
                     Ok(None)
                 } else {
@@ -143,6 +201,260 @@ impl Simulator {
     }
 }
 
+impl Simulator {
+    // クロックを n 回進め、その間に発生した状態の変化を時刻付きで返す.
+    //   ・JS から next() を 1 クロックごとに呼ぶと wasm の境界を何百万回も
+    //     跨ぐことになるので、ループはこちら側で回す.
+    pub fn run_cycles(&mut self, n: u64) -> Result<Vec<StateChange>, String> {
+        let mut changes = vec![];
+        for _ in 0..n {
+            if let Some(state) = self.next()? {
+                changes.push(self.state_change(state));
+            }
+        }
+        Ok(changes)
+    }
+
+    // 指定した時間 [s] だけシミュレーションを進める.
+    //   ・クロックの時刻と一致しないブレークポイントでは、時間の刻みをその時刻で区切り、
+    //     波形の角（パルスの立ち上がりの終わりなど）の状態も返す.
+    pub fn run_for(&mut self, duration: f64) -> Result<Vec<StateChange>, String> {
+        let n = (duration * CLOCK_FREQUENCY).round().max(0.0) as u64;
+        let end = self.cycle + n;
+        let mut points = self
+            .breakpoints(self.time(), end as f64 / CLOCK_FREQUENCY)
+            .into_iter()
+            .filter(|t| {
+                let cycle = t * CLOCK_FREQUENCY;
+                (cycle - cycle.round()).abs() > 1e-6
+            })
+            .peekable();
+        let mut changes = vec![];
+        while self.cycle < end {
+            let next = (self.cycle + 1) as f64 / CLOCK_FREQUENCY;
+            while let Some(time) = points.next_if(|t| *t < next) {
+                if let Some(state) = self.seek(time)? {
+                    let mut change = self.state_change(state);
+                    change.time = time;
+                    changes.push(change);
+                }
+            }
+            if let Some(state) = self.next()? {
+                changes.push(self.state_change(state));
+            }
+        }
+        Ok(changes)
+    }
+
+    // クロックの間の時刻 time [s] における状態を求める. 変化がある場合だけ値を返す.
+    //   ・クロックは進めないので、平均値には含めない.
+    fn seek(&mut self, time: f64) -> Result<Option<State>, String> {
+        if self.state.is_none() {
+            return Err("no state calculated".to_string());
+        }
+        for element in self.elements.values() {
+            element.borrow().seek(time);
+        }
+        let state = self.state()?;
+        if self.state.as_ref() == Some(&state) {
+            Ok(None)
+        } else {
+            self.state = Some(state.clone());
+            Ok(Some(state))
+        }
+    }
+
+    // 状態が変化するまで（最大 max_cycles 回）クロックを進める.
+    //   ・変化しなかった場合は空の Vec を返す.
+    pub fn run_until_change(&mut self, max_cycles: u64) -> Result<Vec<StateChange>, String> {
+        for _ in 0..max_cycles {
+            if let Some(state) = self.next()? {
+                return Ok(vec![self.state_change(state)]);
+            }
+        }
+        Ok(vec![])
+    }
+
+    // from < t <= to の範囲で、独立電源の波形が不連続に変わる時刻を昇順に返す.
+    //   ・時間の刻みは MCU のクロックなので、クロックの時刻に丸めて使うこと.
+    pub fn breakpoints(&self, from: f64, to: f64) -> Vec<f64> {
+        let mut points: Vec<f64> = self
+            .elements
+            .values()
+            .flat_map(|element| element.borrow().breakpoints(from, to))
+            .collect();
+        points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        points.dedup();
+        points
+    }
+
+    fn state_change(&self, state: State) -> StateChange {
+        StateChange {
+            cycle: self.cycle,
+            time: self.time(),
+            elements: state.1.clone(),
+            state,
+            average: self.average(),
+        }
+    }
+
+    // 回路素子の種類、ピン、パラメータ
+    pub fn element_info(&self, element_id: ElementId) -> Result<ElementInfo, String> {
+        let element = self.element(element_id)?;
+        let element = element.borrow();
+        let pins = element
+            .pin_names()
+            .into_iter()
+            .zip(element.pin_nodes())
+            .map(|(name, node_id)| PinInfo { name, node_id })
+            .collect();
+        let params = element
+            .params()
+            .iter()
+            .filter_map(|info| element.get_param(info.name).map(|v| (info.name, v)))
+            .collect();
+        Ok(ElementInfo {
+            id: element_id,
+            kind: element.kind(),
+            name: self.element_names.get(&element_id).cloned(),
+            pins,
+            params,
+        })
+    }
+
+    // 全ての回路素子の情報 (id 順)
+    pub fn elements_info(&self) -> Vec<ElementInfo> {
+        self.elements
+            .keys()
+            .map(|id| self.element_info(*id).unwrap())
+            .collect()
+    }
+
+    // ノードに結合している (element_id, pin_id) の一覧
+    pub fn node_elements(&self, node_id: NodeId) -> Vec<(ElementId, PinId)> {
+        let mut pins = vec![];
+        for (element_id, element) in self.elements.iter() {
+            for (pin_id, node) in element.borrow().pin_nodes().iter().enumerate() {
+                if *node == node_id {
+                    pins.push((*element_id, pin_id));
+                }
+            }
+        }
+        pins
+    }
+
+    // 回路素子の変更できるパラメータの一覧
+    pub fn params(&self, element_id: ElementId) -> Result<Vec<ParamInfo>, String> {
+        let element = self.element(element_id)?;
+        let params = element.borrow().params();
+        Ok(params)
+    }
+
+    // 名前を指定したパラメータの単位と範囲
+    pub fn param_info(&self, element_id: ElementId, name: &str) -> Result<ParamInfo, String> {
+        self.params(element_id)?
+            .into_iter()
+            .find(|info| info.name == name)
+            .ok_or(format!("unknown parameter: {}", name))
+    }
+
+    pub fn get_param(&self, element_id: ElementId, name: &str) -> Result<f32, String> {
+        let element = self.element(element_id)?;
+        let value = element.borrow().get_param(name);
+        value.ok_or(format!("unknown parameter: {}", name))
+    }
+
+    // 回路素子のパラメータを名前で変更する
+    //   ・例: sim.set_param(element_id, "resistance", 1000.0)
+    pub fn set_param(
+        &mut self,
+        element_id: ElementId,
+        name: &str,
+        value: f32,
+    ) -> Result<(), String> {
+        let element = self.element(element_id)?;
+        let info = self.param_info(element_id, name)?;
+        if !(info.min <= value && value <= info.max) {
+            return Err(format!(
+                "{} is out of range: {} ({} - {})",
+                name, value, info.min, info.max
+            ));
+        }
+        element.borrow_mut().set_param(name, value)?;
+        self.state = None;
+        Ok(())
+    }
+
+    pub(crate) fn element(
+        &self,
+        element_id: ElementId,
+    ) -> Result<Rc<RefCell<dyn Element>>, String> {
+        self.elements
+            .get(&element_id)
+            .cloned()
+            .ok_or(format!("unknown element: {}", element_id))
+    }
+
+    // 各回路素子の端子に流れ込む電流（瞬時値）
+    pub fn currents(&self) -> BTreeMap<ElementId, Vec<f32>> {
+        let mut currents = BTreeMap::new();
+        for (element_id, element) in self.elements.iter() {
+            let element_currents = match &self.equation {
+                Some(eq) => element.borrow().currents(eq),
+                None => vec![],
+            };
+            if !element_currents.is_empty() {
+                currents.insert(*element_id, element_currents);
+            }
+        }
+        currents
+    }
+
+    // MCU の各ピンの論理レベル
+    pub fn pin_levels(&self) -> BTreeMap<ElementId, Vec<bool>> {
+        let mut levels = BTreeMap::new();
+        for (element_id, element) in self.elements.iter() {
+            let element_levels = element.borrow().pin_levels();
+            if !element_levels.is_empty() {
+                levels.insert(*element_id, element_levels);
+            }
+        }
+        levels
+    }
+
+    // 時間平均を取る窓の幅 [s] を設定する. 0 以下を指定すると無効になる.
+    pub fn set_averaging_window(&mut self, window: f64) {
+        if window <= 0.0 {
+            self.averager = None;
+            return;
+        }
+        let mut averager = Averager::new((window * CLOCK_FREQUENCY).round() as u64);
+        if let Some(state) = self.state.clone() {
+            averager.push(self.cycle, state.0, self.currents(), self.pin_levels());
+        }
+        self.averager = Some(averager);
+    }
+
+    // 直近の窓で時間平均した状態
+    pub fn average(&self) -> Option<Average> {
+        self.averager
+            .as_ref()
+            .and_then(|averager| averager.average(self.cycle))
+    }
+
+    fn record_average(&mut self, state: &State) {
+        if self.averager.is_none() {
+            return;
+        }
+        let currents = self.currents();
+        let levels = self.pin_levels();
+        let cycle = self.cycle;
+        if let Some(averager) = self.averager.as_mut() {
+            averager.push(cycle, state.0.clone(), currents, levels);
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Link {
     element_id: ElementId,
@@ -153,19 +465,34 @@ pub struct Link {
 impl Link {
     fn new(element_id: ElementId, pin_id: PinId, node_id: NodeId) -> Link {
         Link {
-            element_id: element_id,
-            pin_id: pin_id,
-            node_id: node_id,
+            element_id,
+            pin_id,
+            node_id,
         }
     }
 }
 
-#[derive(Debug, Default, PartialEq, Clone)]
-pub struct State(BTreeMap<NodeId, f32>);
+// 回路素子ごとの出力値（LED の明るさなど）. 名前 -> 値.
+pub type ElementState = BTreeMap<&'static str, f32>;
+
+// 各ノードの電圧と、出力値を持つ回路素子の状態
+#[derive(Debug, Default, Clone)]
+pub struct State(
+    pub BTreeMap<NodeId, f32>,
+    pub BTreeMap<ElementId, ElementState>,
+);
+
+// 変化の検出に使うので、Serialize と同じくノードの電圧だけを比べる.
+//   ・回路素子の出力値（LED の明るさなど）はノードの電圧に伴って変わる.
+impl PartialEq for State {
+    fn eq(&self, other: &State) -> bool {
+        self.0 == other.0
+    }
+}
 
 impl State {
     pub fn new(map: BTreeMap<NodeId, f32>) -> State {
-        State(map)
+        State(map, BTreeMap::new())
     }
 }
 
@@ -175,6 +502,7 @@ impl Serialize for State {
     where
         S: Serializer,
     {
+        // 回路素子の出力値は含めない（StateChange.elements や wasm の element_outputs で取得する）.
         let mut map = serializer.serialize_map(Some(self.0.len()))?;
         for (k, v) in &self.0 {
             map.serialize_entry(&k.to_string(), &v)?;
@@ -183,6 +511,35 @@ impl Serialize for State {
     }
 }
 
+// 時刻付きの状態の変化
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct StateChange {
+    pub cycle: u64,
+    pub time: f64,
+    pub state: State,
+    // 回路素子の出力値 (LED の明るさなど). element_id -> 名前 -> 値.
+    pub elements: BTreeMap<ElementId, ElementState>,
+    // 時間平均が有効な場合はその時点の平均値
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub average: Option<Average>,
+}
+
+// 回路素子の情報
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ElementInfo {
+    pub id: ElementId,
+    pub kind: &'static str,
+    pub name: Option<String>,
+    pub pins: Vec<PinInfo>,
+    pub params: BTreeMap<&'static str, f32>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct PinInfo {
+    pub name: &'static str,
+    pub node_id: NodeId,
+}
+
 // Ax = z
 #[derive(Debug)]
 pub struct Equation {
@@ -194,6 +551,79 @@ pub struct Equation {
     pub node_index: BTreeMap<NodeId, usize>,
     // src となっている Element の element_id が方程式の何段目に当たるか.
     pub src_index: BTreeMap<(ElementId, PinId), usize>,
+    // 回路素子の内部ノード (element_id, 内部ノードの番号) が方程式の何段目に当たるか.
+    //   ・内部ノードの段は src の段の後に置く. 値は電圧で、電流ではない.
+    pub internal_index: BTreeMap<(ElementId, usize), usize>,
+}
+
+impl Equation {
+    // ノードが方程式の何段目に当たるか. GND は None.
+    pub fn node(&self, node_id: NodeId) -> Option<usize> {
+        self.node_index.get(&node_id).cloned()
+    }
+
+    // src となっているピンが方程式の何段目に当たるか.
+    pub fn src(&self, element_id: ElementId, pin_id: PinId) -> Option<usize> {
+        self.src_index
+            .get(&(element_id, pin_id))
+            .map(|index| index + self.node_index.len())
+    }
+
+    // 回路素子の内部ノードが方程式の何段目に当たるか.
+    pub fn internal(&self, element_id: ElementId, k: usize) -> Option<usize> {
+        self.internal_index
+            .get(&(element_id, k))
+            .map(|index| index + self.node_index.len() + self.src_index.len())
+    }
+
+    // index 段目が src の電流か（ノードや内部ノードの電圧ではないか）
+    pub fn is_current(&self, index: usize) -> bool {
+        let nodes = self.node_index.len();
+        nodes <= index && index < nodes + self.src_index.len()
+    }
+
+    // x の index 段目の値. None (GND) は 0.
+    pub fn value(&self, index: Option<usize>) -> f32 {
+        index.map_or(0.0, |index| self.x[index])
+    }
+
+    // A の (row, col) に値を加える. どちらかが None (GND) の場合は何もしない.
+    pub fn add_a(&mut self, row: Option<usize>, col: Option<usize>, value: f32) {
+        if let (Some(row), Some(col)) = (row, col) {
+            self.a[(row, col)] += value;
+        }
+    }
+
+    // z の row 段目に値を加える. None (GND) の場合は何もしない.
+    pub fn add_z(&mut self, row: Option<usize>, value: f32) {
+        if let Some(row) = row {
+            self.z[row] += value;
+        }
+    }
+
+    // ノードの電圧. GND は 0V.
+    pub fn voltage(&self, node_id: NodeId) -> f32 {
+        self.value(self.node(node_id))
+    }
+
+    // ノード n0, n1 の間にコンダクタンス g のスタンプを押す. GND 側は省略する.
+    pub fn stamp_conductance(&mut self, n0: NodeId, n1: NodeId, g: f32) {
+        let (i0, i1) = (self.node(n0), self.node(n1));
+        self.stamp_conductance_index(i0, i1, g);
+    }
+
+    // 方程式の i0, i1 段目の間にコンダクタンス g のスタンプを押す.
+    pub fn stamp_conductance_index(&mut self, i0: Option<usize>, i1: Option<usize>, g: f32) {
+        self.add_a(i0, i0, g);
+        self.add_a(i1, i1, g);
+        self.add_a(i0, i1, -g);
+        self.add_a(i1, i0, -g);
+    }
+
+    // src となっているピンに流れ込む電流
+    pub fn src_current(&self, element_id: ElementId, pin_id: PinId) -> f32 {
+        self.value(self.src(element_id, pin_id))
+    }
 }
 
 #[derive(Debug)]
@@ -204,7 +634,7 @@ pub enum EqSolveError {
 
 impl Simulator {
     // 回路の状態ベクトルの次元.
-    // 次元 = ノードの数 + 電圧/電流源となっているピンの数.
+    // 次元 = ノードの数 + 電圧/電流源となっているピンの数 + 回路素子の内部ノードの数.
     fn equation_dim(&self) -> usize {
         self.nodes.len() - 1 // don't count GND node.
             + self
@@ -218,6 +648,7 @@ impl Simulator {
                              .filter(|&is_output| *is_output)
                              .collect::<Vec<&bool>>()
                              .len();
+                    sum += element.borrow().internal_nodes();
                     sum
                 })
     }
@@ -230,6 +661,7 @@ impl Simulator {
             z: DVector::<f32>::zeros(dim),
             node_index: BTreeMap::new(),
             src_index: BTreeMap::new(),
+            internal_index: BTreeMap::new(),
         };
 
         for (index, node_id) in self.nodes.iter().enumerate() {
@@ -249,6 +681,14 @@ impl Simulator {
             }
         }
 
+        let mut index = 0;
+        for (element_id, element) in self.elements.iter() {
+            for k in 0..element.borrow().internal_nodes() {
+                eq.internal_index.insert((*element_id, k), index);
+                index += 1;
+            }
+        }
+
         eq
     }
 
@@ -277,7 +717,12 @@ impl Simulator {
             // println!("||| eq.node_index: {:?}", eq.node_index);
             // println!("||| eq.src_index: {:?}", eq.src_index);
             // println!("l2:  {}", l2norm);
-            if l2norm < SOLVER_ACCURACY {
+            // 接合電圧を制限している間は、スタンプが x と対応しないので収束とみなさない.
+            let is_limited = self
+                .elements
+                .values()
+                .any(|element| element.borrow().limited());
+            if l2norm < SOLVER_ACCURACY && !is_limited {
                 return Ok(eq);
             }
 
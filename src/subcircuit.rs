@@ -0,0 +1,485 @@
+use super::elements::battery::BatteryChemistry;
+use super::elements::led::LedColor;
+use super::elements::switch::SwitchKind;
+use super::simulator::*;
+use serde::*;
+use std::collections::BTreeMap;
+
+// サブサーキットの入れ子の上限. 自分自身を含む定義で無限に展開しないように.
+const MAX_DEPTH: usize = 16;
+
+// 回路素子の値. 数値か、サブサーキットのパラメータ名.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Number(f32),
+    Param(String),
+}
+
+impl Value {
+    fn resolve(&self, params: &BTreeMap<String, f32>) -> Result<f32, String> {
+        match self {
+            Value::Number(v) => Ok(*v),
+            Value::Param(name) => params
+                .get(name)
+                .cloned()
+                .ok_or(format!("unknown parameter: {}", name)),
+        }
+    }
+}
+
+impl From<f32> for Value {
+    fn from(v: f32) -> Value {
+        Value::Number(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(name: &str) -> Value {
+        Value::Param(name.to_string())
+    }
+}
+
+// サブサーキットに含められる回路素子
+//   ・JSON では {"type": "registor", "value": 1000} のように書く.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SubElement {
+    Registor {
+        value: Value,
+    },
+    Diode,
+    Zener {
+        bv: Value,
+    },
+    Led {
+        color: LedColor,
+    },
+    IndVoltageSrc {
+        value: Value,
+    },
+    IndCurrentSrc {
+        value: Value,
+    },
+    Potentiometer {
+        value: Value,
+    },
+    Npn,
+    Pnp,
+    Nmos,
+    Pmos,
+    Opamp,
+    IdealOpamp,
+    Vcvs {
+        gain: Value,
+    },
+    Vccs {
+        gain: Value,
+    },
+    // 電流制御電源. control は同じサブサーキット内で先に定義した電圧源などの名前 ("V1", "XA.V1").
+    Ccvs {
+        gain: Value,
+        control: String,
+    },
+    Cccs {
+        gain: Value,
+        control: String,
+    },
+    Switch {
+        kind: SwitchKind,
+        // 初期状態 (ON: true)
+        #[serde(default)]
+        on: bool,
+    },
+    Battery {
+        chemistry: BatteryChemistry,
+    },
+    // 他のサブサーキットのインスタンス
+    Subcircuit {
+        subcircuit: String,
+        #[serde(default)]
+        params: BTreeMap<String, Value>,
+    },
+}
+
+impl SubElement {
+    // 接続できるピンの数 (最小, 最大)
+    fn pin_count(&self) -> (usize, usize) {
+        match self {
+            SubElement::Potentiometer { .. }
+            | SubElement::Npn
+            | SubElement::Pnp
+            | SubElement::Opamp
+            | SubElement::IdealOpamp => (3, 3),
+            // バルクは省略できる
+            SubElement::Nmos | SubElement::Pmos => (3, 4),
+            SubElement::Vcvs { .. } | SubElement::Vccs { .. } => (4, 4),
+            SubElement::Switch {
+                kind: SwitchKind::Spdt,
+                ..
+            } => (3, 3),
+            SubElement::Subcircuit { .. } => (0, usize::MAX),
+            _ => (2, 2),
+        }
+    }
+
+    fn values(&self) -> Vec<&Value> {
+        match self {
+            SubElement::Registor { value }
+            | SubElement::IndVoltageSrc { value }
+            | SubElement::IndCurrentSrc { value }
+            | SubElement::Potentiometer { value } => vec![value],
+            SubElement::Zener { bv } => vec![bv],
+            SubElement::Vcvs { gain }
+            | SubElement::Vccs { gain }
+            | SubElement::Ccvs { gain, .. }
+            | SubElement::Cccs { gain, .. } => vec![gain],
+            _ => vec![],
+        }
+    }
+
+    fn control(&self) -> Option<&str> {
+        match self {
+            SubElement::Ccvs { control, .. } | SubElement::Cccs { control, .. } => Some(control),
+            _ => None,
+        }
+    }
+
+    // 電流制御電源の制御に使える（電流を方程式の変数に持つ）回路素子か
+    fn has_branch_current(&self) -> bool {
+        matches!(
+            self,
+            SubElement::IndVoltageSrc { .. }
+                | SubElement::Vcvs { .. }
+                | SubElement::Ccvs { .. }
+                | SubElement::Battery { .. }
+        )
+    }
+
+    // 回路素子を作成する. 値と制御する回路素子は展開時に解決できることを確認済み.
+    fn add(
+        &self,
+        sim: &mut Simulator,
+        params: &BTreeMap<String, f32>,
+        control: Option<ElementId>,
+    ) -> ElementId {
+        let v = |value: &Value| value.resolve(params).unwrap();
+        match self {
+            SubElement::Registor { value } => sim.add_registor(v(value)),
+            SubElement::Diode => sim.add_diode(),
+            SubElement::Zener { bv } => sim.add_zener(v(bv)),
+            SubElement::Led { color } => sim.add_led(*color),
+            SubElement::IndVoltageSrc { value } => sim.add_ind_voltage_src(v(value)),
+            SubElement::IndCurrentSrc { value } => sim.add_ind_current_src(v(value)),
+            SubElement::Potentiometer { value } => sim.add_potentiometer(v(value)),
+            SubElement::Npn => sim.add_npn(),
+            SubElement::Pnp => sim.add_pnp(),
+            SubElement::Nmos => sim.add_nmos(),
+            SubElement::Pmos => sim.add_pmos(),
+            SubElement::Opamp => sim.add_opamp(),
+            SubElement::IdealOpamp => sim.add_ideal_opamp(),
+            SubElement::Vcvs { gain } => sim.add_vcvs(v(gain)),
+            SubElement::Vccs { gain } => sim.add_vccs(v(gain)),
+            SubElement::Ccvs { gain, .. } => sim.add_ccvs(v(gain), control.unwrap()).unwrap(),
+            SubElement::Cccs { gain, .. } => sim.add_cccs(v(gain), control.unwrap()).unwrap(),
+            SubElement::Switch { kind, on } => {
+                let id = sim.add_switch(*kind);
+                sim.set_switch_state(id, *on);
+                id
+            }
+            SubElement::Battery { chemistry } => sim.add_battery(*chemistry),
+            SubElement::Subcircuit { .. } => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SubcircuitElement {
+    pub name: String,
+    #[serde(flatten)]
+    pub element: SubElement,
+    // ピンの順に、接続するネットの名前.
+    //   ・ポート名は外部のノード、"0" は GND、それ以外はサブサーキット内部のノード.
+    pub nodes: Vec<String>,
+}
+
+// サブサーキットの定義
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct Subcircuit {
+    pub ports: Vec<String>,
+    // パラメータとそのデフォルト値
+    #[serde(default)]
+    pub params: BTreeMap<String, f32>,
+    pub elements: Vec<SubcircuitElement>,
+}
+
+impl Subcircuit {
+    pub fn new(ports: &[&str]) -> Subcircuit {
+        Subcircuit {
+            ports: ports.iter().map(|p| p.to_string()).collect(),
+            params: BTreeMap::new(),
+            elements: vec![],
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Subcircuit, String> {
+        serde_json::from_str(json).map_err(|err| format!("invalid subcircuit: {}", err))
+    }
+
+    pub fn param(mut self, name: &str, default: f32) -> Subcircuit {
+        self.params.insert(name.to_string(), default);
+        self
+    }
+
+    pub fn element(mut self, name: &str, element: SubElement, nodes: &[&str]) -> Subcircuit {
+        self.elements.push(SubcircuitElement {
+            name: name.to_string(),
+            element,
+            nodes: nodes.iter().map(|n| n.to_string()).collect(),
+        });
+        self
+    }
+}
+
+// 展開後のネット
+#[derive(Debug, Clone)]
+enum Net {
+    Node(NodeId),
+    // サブサーキット内部のノード. 階層名で区別する.
+    Local(String),
+}
+
+// 展開後の回路素子
+struct FlatElement {
+    name: String,
+    element: SubElement,
+    params: BTreeMap<String, f32>,
+    nets: Vec<Net>,
+    // 電流制御電源が制御に使う回路素子の階層名
+    control: Option<String>,
+}
+
+impl Simulator {
+    pub fn define_subcircuit(&mut self, name: &str, subcircuit: Subcircuit) {
+        self.subcircuits.insert(name.to_string(), subcircuit);
+    }
+
+    // サブサーキット name のインスタンス instance を作成し、ports のノードに接続する.
+    //   ・回路素子と内部ノードには "X1.R2" のような階層名が付く. 既に使われているインスタンス名はエラー.
+    //   ・展開に失敗した場合は回路を変更しない.
+    pub fn add_subcircuit(
+        &mut self,
+        instance: &str,
+        name: &str,
+        ports: &[NodeId],
+        params: &[(&str, f32)],
+    ) -> Result<(), String> {
+        let prefix = format!("{}.", instance);
+        let is_used = |names: &BTreeMap<usize, String>| {
+            names
+                .values()
+                .any(|n| n.as_str() == instance || n.starts_with(&prefix))
+        };
+        if is_used(&self.element_names) || is_used(&self.node_names) {
+            return Err(format!("duplicate instance: {}", instance));
+        }
+
+        let overrides = params
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::Number(*v)))
+            .collect();
+        let ports = ports.iter().map(|node_id| Net::Node(*node_id)).collect();
+        let mut flat = vec![];
+        self.flatten(
+            instance,
+            name,
+            ports,
+            &overrides,
+            &BTreeMap::new(),
+            0,
+            &mut flat,
+        )?;
+
+        let mut locals: BTreeMap<String, NodeId> = BTreeMap::new();
+        let mut ids: BTreeMap<String, ElementId> = BTreeMap::new();
+        for element in flat {
+            let control = element.control.as_ref().map(|name| ids[name]);
+            let element_id = element.element.add(self, &element.params, control);
+            ids.insert(element.name.clone(), element_id);
+            self.element_names.insert(element_id, element.name);
+            for (pin_id, net) in element.nets.iter().enumerate() {
+                let node_id = match net {
+                    Net::Node(node_id) => *node_id,
+                    Net::Local(name) => match locals.get(name) {
+                        Some(node_id) => *node_id,
+                        None => {
+                            let node_id = self.add_node();
+                            self.node_names.insert(node_id, name.clone());
+                            locals.insert(name.clone(), node_id);
+                            node_id
+                        }
+                    },
+                };
+                self.connect_element_pin_node(element_id, pin_id, node_id);
+            }
+        }
+        Ok(())
+    }
+
+    // 入れ子のサブサーキットを再帰的に展開する. 回路はまだ変更しない.
+    //   ・再帰の途中の状態（名前の接頭辞、パラメータ、深さ）を引数で受け渡す.
+    #[allow(clippy::too_many_arguments)]
+    fn flatten(
+        &self,
+        prefix: &str,
+        name: &str,
+        ports: Vec<Net>,
+        overrides: &BTreeMap<String, Value>,
+        outer_params: &BTreeMap<String, f32>,
+        depth: usize,
+        flat: &mut Vec<FlatElement>,
+    ) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("subcircuit nesting too deep: {}", prefix));
+        }
+        let subcircuit = self
+            .subcircuits
+            .get(name)
+            .ok_or(format!("unknown subcircuit: {}", name))?;
+        if ports.len() != subcircuit.ports.len() {
+            return Err(format!(
+                "{}: {} expects {} ports, got {}",
+                prefix,
+                name,
+                subcircuit.ports.len(),
+                ports.len()
+            ));
+        }
+
+        let mut params = subcircuit.params.clone();
+        for (key, value) in overrides.iter() {
+            if !params.contains_key(key) {
+                return Err(format!("{}: unknown parameter: {}", prefix, key));
+            }
+            params.insert(key.clone(), value.resolve(outer_params)?);
+        }
+
+        let mut nets: BTreeMap<&str, Net> = subcircuit
+            .ports
+            .iter()
+            .map(|port| port.as_str())
+            .zip(ports)
+            .collect();
+        nets.insert("0", Net::Node(0));
+
+        for element in subcircuit.elements.iter() {
+            let path = format!("{}.{}", prefix, element.name);
+            let element_nets: Vec<Net> = element
+                .nodes
+                .iter()
+                .map(|node| match nets.get(node.as_str()) {
+                    Some(net) => net.clone(),
+                    None => Net::Local(format!("{}.{}", prefix, node)),
+                })
+                .collect();
+
+            if let SubElement::Subcircuit {
+                subcircuit: sub,
+                params: sub_params,
+            } = &element.element
+            {
+                self.flatten(
+                    &path,
+                    sub,
+                    element_nets,
+                    sub_params,
+                    &params,
+                    depth + 1,
+                    flat,
+                )?;
+                continue;
+            }
+
+            let (min, max) = element.element.pin_count();
+            if element_nets.len() < min || max < element_nets.len() {
+                return Err(format!("{}: wrong number of nodes", path));
+            }
+            for value in element.element.values() {
+                value
+                    .resolve(&params)
+                    .map_err(|err| format!("{}: {}", path, err))?;
+            }
+            let control = match element.element.control() {
+                Some(control) => {
+                    let control = format!("{}.{}", prefix, control);
+                    match flat.iter().find(|e| e.name == control) {
+                        Some(e) if e.element.has_branch_current() => Some(control),
+                        Some(_) => {
+                            return Err(format!("{}: {} has no branch current", path, control))
+                        }
+                        None => return Err(format!("{}: unknown control: {}", path, control)),
+                    }
+                }
+                None => None,
+            };
+            flat.push(FlatElement {
+                name: path,
+                element: element.element.clone(),
+                params: params.clone(),
+                nets: element_nets,
+                control,
+            });
+        }
+        Ok(())
+    }
+}
+
+// 回路素子とノードを名前で表した状態. 名前がない場合は id.
+#[derive(Debug, Default, PartialEq, Clone, Serialize)]
+pub struct NamedState {
+    pub voltages: BTreeMap<String, f32>,
+    pub elements: BTreeMap<String, ElementState>,
+}
+
+impl Simulator {
+    pub fn name_element(&mut self, element_id: ElementId, name: &str) {
+        self.element_names.insert(element_id, name.to_string());
+    }
+
+    pub fn name_node(&mut self, node_id: NodeId, name: &str) {
+        self.node_names.insert(node_id, name.to_string());
+    }
+
+    pub fn element_id(&self, name: &str) -> Option<ElementId> {
+        self.element_names
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(id, _)| *id)
+    }
+
+    pub fn node_id(&self, name: &str) -> Option<NodeId> {
+        self.node_names
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(id, _)| *id)
+    }
+
+    pub fn named_state(&self, state: &State) -> NamedState {
+        NamedState {
+            voltages: state
+                .0
+                .iter()
+                .map(|(id, v)| (name_or_id(&self.node_names, *id), *v))
+                .collect(),
+            elements: state
+                .1
+                .iter()
+                .map(|(id, outputs)| (name_or_id(&self.element_names, *id), outputs.clone()))
+                .collect(),
+        }
+    }
+}
+
+fn name_or_id(names: &BTreeMap<usize, String>, id: usize) -> String {
+    names.get(&id).cloned().unwrap_or(id.to_string())
+}
@@ -0,0 +1,156 @@
+use super::ac::AcPoint;
+use super::operating_point::OperatingPoint;
+use super::simulator::*;
+use serde::*;
+
+// 掃引する値
+//   ・JSON では {"list": [100, 220, 330]}, {"linear": {"start": 0, "stop": 5, "step": 0.5}},
+//     {"decade": {"start": 10, "stop": 100000, "points": 10}} のように書く.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SweepValues {
+    List(Vec<f32>),
+    Linear { start: f32, stop: f32, step: f32 },
+    // 1 桁あたり points 点の対数掃引
+    Decade { start: f32, stop: f32, points: u32 },
+}
+
+impl SweepValues {
+    pub fn values(&self) -> Result<Vec<f32>, String> {
+        match self {
+            SweepValues::List(values) => Ok(values.clone()),
+            SweepValues::Linear { start, stop, step } => {
+                let n = (stop - start) / step;
+                if !n.is_finite() || n < 0.0 {
+                    return Err(format!("invalid step: {}", step));
+                }
+                // 誤差が積み重ならないように、毎回 start から求める
+                let n = (n + 1e-3).floor() as usize;
+                Ok((0..=n).map(|i| start + step * i as f32).collect())
+            }
+            SweepValues::Decade {
+                start,
+                stop,
+                points,
+            } => {
+                if *start <= 0.0 || stop < start || *points == 0 {
+                    return Err("invalid decade sweep".to_string());
+                }
+                let decades = (stop / start).log10();
+                let n = (decades * *points as f32 + 1e-3).floor() as usize;
+                Ok((0..=n)
+                    .map(|i| start * 10f32.powf(i as f32 / *points as f32))
+                    .collect())
+            }
+        }
+    }
+}
+
+// 各値で実行する解析
+//   ・JSON では "operating_point", {"transient": {"duration": 0.001}},
+//     {"ac": {"frequencies": {"decade": {"start": 10, "stop": 100000, "points": 10}}}}.
+//   ・電源の値を掃引した動作点の解析は DC 掃引になる.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Analysis {
+    OperatingPoint,
+    // 現在の時刻から duration [s] の過渡解析
+    Transient { duration: f64 },
+    // 動作点での小信号 AC 解析
+    Ac { frequencies: SweepValues },
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnalysisResult {
+    OperatingPoint(OperatingPoint),
+    Transient(Vec<StateChange>),
+    Ac(Vec<AcPoint>),
+}
+
+// パラメータの値と、その値での解析結果
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct SweepPoint {
+    pub value: f32,
+    pub result: AnalysisResult,
+}
+
+impl Simulator {
+    // 回路素子のパラメータを掃引し、各値で解析を実行する (SPICE の .STEP に相当する)
+    //   ・過渡解析は毎回同じ時刻・同じ内部状態（電池の残量など）から始める.
+    //     内部状態を戻せない MCU を含む回路ではエラーになる.
+    //   ・終了後はパラメータと時刻を元に戻す.
+    pub fn step(
+        &mut self,
+        element_id: ElementId,
+        name: &str,
+        values: &SweepValues,
+        analysis: &Analysis,
+    ) -> Result<Vec<SweepPoint>, String> {
+        let values = values.values()?;
+        let original = self.get_param(element_id, name)?;
+        let start = self.begin_analysis(analysis)?;
+
+        let mut points = vec![];
+        let mut result = Ok(());
+        for value in values {
+            result = self
+                .set_param(element_id, name, value)
+                .and_then(|_| self.analyze(analysis, start))
+                .map(|r| points.push(SweepPoint { value, result: r }));
+            if result.is_err() {
+                break;
+            }
+        }
+
+        // 途中でエラーになっても、パラメータと時刻は必ず元に戻す
+        let restored = self.set_param(element_id, name, original);
+        self.rewind(start);
+        let updated = self.update_state();
+        result.and(restored).and(updated).map(|_| points)
+    }
+
+    // 時刻 start から解析を実行する
+    pub fn analyze(&mut self, analysis: &Analysis, start: u64) -> Result<AnalysisResult, String> {
+        self.rewind(start);
+        self.update_state()?;
+        match analysis {
+            Analysis::OperatingPoint => Ok(AnalysisResult::OperatingPoint(self.operating_point()?)),
+            Analysis::Transient { duration } => {
+                Ok(AnalysisResult::Transient(self.run_for(*duration)?))
+            }
+            Analysis::Ac { frequencies } => Ok(AnalysisResult::Ac(self.ac_analysis(frequencies)?)),
+        }
+    }
+
+    // 解析を繰り返せるように、現在の時刻と内部状態を保存して時刻を返す.
+    //   ・過渡解析以外は時刻を進めないので、内部状態を戻せない素子があってもよい.
+    pub fn begin_analysis(&mut self, analysis: &Analysis) -> Result<u64, String> {
+        let checkpoint = self.checkpoint();
+        match analysis {
+            Analysis::Transient { .. } => checkpoint,
+            _ => Ok(self.cycle),
+        }
+    }
+
+    // 現在の時刻と各回路素子の内部状態を保存し、時刻を返す. rewind でこの時刻に戻せる.
+    //   ・内部状態を戻せない素子がある場合はエラー.
+    pub fn checkpoint(&mut self) -> Result<u64, String> {
+        let mut result = Ok(self.cycle);
+        for element in self.elements.values() {
+            if let Err(err) = element.borrow_mut().save() {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    // シミュレーション時刻を cycle (checkpoint の時刻) に戻す. 状態は計算待ちになる.
+    pub fn rewind(&mut self, cycle: u64) {
+        self.cycle = cycle;
+        for element in self.elements.values() {
+            element.borrow_mut().rewind(cycle);
+        }
+        self.state = None;
+    }
+}
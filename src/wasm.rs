@@ -1,5 +1,14 @@
+use super::elements::battery::BatteryChemistry;
+use super::elements::bjt::BjtModel;
+use super::elements::led::LedColor;
+use super::elements::mosfet::{MosfetLevel, MosfetModel};
+use super::elements::opamp::OpAmpModel;
+use super::elements::waveform::Waveform;
+use super::monte_carlo::{Distribution, Probe, Tolerance};
 use super::simulator::*;
-use serde_json::*;
+use super::subcircuit::Subcircuit;
+use super::sweep::{Analysis, SweepValues};
+use std::collections::BTreeMap;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -32,6 +41,59 @@ impl Circuit {
         }
     }
 
+    // 最後に求めた状態での回路素子の出力値 (LED の明るさなど) を JSON で返す
+    //   ・{"3": {"brightness": 1.0, ...}} のように element_id ごとにまとめる
+    //   ・状態が計算されていない場合は None が返される
+    pub fn element_outputs(&self) -> Option<String> {
+        self.0
+            .state
+            .as_ref()
+            .map(|state| serde_json::to_string(&state.1).unwrap())
+    }
+
+    // クロックを n 回進め、その間の状態の変化を時刻付きの JSON 配列で返す
+    //   ・エラーの場合は None が返される
+    pub fn run_cycles(&mut self, n: u32) -> Option<String> {
+        match self.0.run_cycles(n as u64) {
+            Ok(changes) => Some(serde_json::to_string(&changes).unwrap()),
+            Err(_) => None,
+        }
+    }
+
+    // 指定した時間 [s] だけ進め、その間の状態の変化を時刻付きの JSON 配列で返す
+    pub fn run_for(&mut self, duration: f64) -> Option<String> {
+        match self.0.run_for(duration) {
+            Ok(changes) => Some(serde_json::to_string(&changes).unwrap()),
+            Err(_) => None,
+        }
+    }
+
+    // 状態が変化するまで（最大 max_cycles 回）クロックを進める
+    pub fn run_until_change(&mut self, max_cycles: u32) -> Option<String> {
+        match self.0.run_until_change(max_cycles as u64) {
+            Ok(changes) => Some(serde_json::to_string(&changes).unwrap()),
+            Err(_) => None,
+        }
+    }
+
+    // 各回路素子の端子に流れ込む電流（瞬時値）を JSON で返す
+    pub fn currents(&self) -> String {
+        serde_json::to_string(&self.0.currents()).unwrap()
+    }
+
+    // 時間平均を取る窓の幅 [s] を設定する. 0 を指定すると無効になる
+    pub fn set_averaging_window(&mut self, window: f64) {
+        self.0.set_averaging_window(window);
+    }
+
+    // 時間平均した状態（ノード電圧・電流・デューティ比）を JSON で返す
+    //   ・時間平均が無効の場合は None が返される
+    pub fn average(&self) -> Option<String> {
+        self.0
+            .average()
+            .map(|average| serde_json::to_string(&average).unwrap())
+    }
+
     //--------------------------------------------------------------------------
     // ノード
 
@@ -65,6 +127,34 @@ impl Circuit {
         self.0.registor_change_registance(element_id, r);
     }
 
+    // >>>> 可変抵抗器
+
+    // 可変抵抗器（B カーブ）を作成する
+    pub fn add_potentiometer(&mut self, r: f32) -> usize {
+        self.0.add_potentiometer(r)
+    }
+
+    // 可変抵抗器（A カーブ）を作成する
+    pub fn add_log_potentiometer(&mut self, r: f32) -> usize {
+        self.0.add_log_potentiometer(r)
+    }
+
+    // 可変抵抗器のワイパーの位置 (0..1) を変化させる
+    pub fn potentiometer_change_position(&mut self, element_id: usize, position: f32) {
+        self.0.potentiometer_change_position(element_id, position);
+    }
+
+    // 可変抵抗器の全抵抗値を変化させる
+    pub fn potentiometer_change_registance(
+        &mut self,
+        element_id: usize,
+        r: f32,
+    ) -> Result<(), JsValue> {
+        self.0
+            .potentiometer_change_registance(element_id, r)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
     // >>>> ダイオード
 
     // ダイオードを作成する
@@ -72,6 +162,201 @@ impl Circuit {
         self.0.add_diode()
     }
 
+    // 降伏電圧 bv のツェナーダイオードを作成する
+    pub fn add_zener(&mut self, bv: f32) -> usize {
+        self.0.add_zener(bv)
+    }
+
+    // ダイオードの降伏電圧, ニー電流, 動作抵抗を設定する. bv <= 0 で降伏しなくなる.
+    pub fn diode_set_breakdown(&mut self, element_id: usize, bv: f32, ibv: f32, rz: f32) {
+        self.0.diode_set_breakdown(element_id, bv, ibv, rz);
+    }
+
+    // >>>> スイッチ
+
+    // 単極単投スイッチを作成する
+    pub fn add_spst_switch(&mut self) -> usize {
+        self.0.add_spst_switch()
+    }
+
+    // 単極双投スイッチを作成する
+    pub fn add_spdt_switch(&mut self) -> usize {
+        self.0.add_spdt_switch()
+    }
+
+    // 押しボタンを作成する
+    pub fn add_pushbutton(&mut self) -> usize {
+        self.0.add_pushbutton()
+    }
+
+    // スイッチを切り替える（押しボタンは true で押す, false で離す）
+    pub fn set_switch_state(&mut self, element_id: usize, on: bool) {
+        self.0.set_switch_state(element_id, on);
+    }
+
+    // 押しボタンを押し、duration [s] 後に離す
+    pub fn press_pushbutton(&mut self, element_id: usize, duration: f64) -> Result<(), JsValue> {
+        self.0
+            .press_pushbutton(element_id, duration)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // スイッチの ON 抵抗 / OFF 抵抗を変化させる
+    pub fn set_switch_resistance(
+        &mut self,
+        element_id: usize,
+        r_on: f32,
+        r_off: f32,
+    ) -> Result<(), JsValue> {
+        self.0
+            .set_switch_resistance(element_id, r_on, r_off)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // スイッチのチャタリングの時間 [s] を設定する
+    pub fn set_switch_bounce(&mut self, element_id: usize, duration: f64) {
+        self.0.set_switch_bounce(element_id, duration);
+    }
+
+    // >>>> バイポーラトランジスタ
+
+    // NPN トランジスタを作成する
+    pub fn add_npn(&mut self) -> usize {
+        self.0.add_npn()
+    }
+
+    // PNP トランジスタを作成する
+    pub fn add_pnp(&mut self) -> usize {
+        self.0.add_pnp()
+    }
+
+    // Gummel-Poon モデルのパラメータを設定する. vaf に 0 を指定するとアーリー効果を無視する
+    //   ・BjtModel は JS に公開していないので、パラメータを個別の引数で受け取る.
+    #[allow(clippy::too_many_arguments)]
+    pub fn bjt_set_model(
+        &mut self,
+        element_id: usize,
+        is: f32,
+        bf: f32,
+        br: f32,
+        vaf: f32,
+        rb: f32,
+        rc: f32,
+        re: f32,
+    ) {
+        let vaf = if vaf > 0.0 { vaf } else { f32::INFINITY };
+        let model = BjtModel {
+            is,
+            bf,
+            br,
+            vaf,
+            rb,
+            rc,
+            re,
+        };
+        self.0.bjt_set_model(element_id, model);
+    }
+
+    // >>>> MOSFET
+
+    // N チャネル MOSFET を作成する
+    pub fn add_nmos(&mut self) -> usize {
+        self.0.add_nmos()
+    }
+
+    // P チャネル MOSFET を作成する
+    pub fn add_pmos(&mut self) -> usize {
+        self.0.add_pmos()
+    }
+
+    // MOSFET のモデルパラメータを設定する. ekv が false の場合は Level 1
+    //   ・bjt_set_model と同じく、パラメータを個別の引数で受け取る.
+    #[allow(clippy::too_many_arguments)]
+    pub fn mosfet_set_model(
+        &mut self,
+        element_id: usize,
+        ekv: bool,
+        vto: f32,
+        kp: f32,
+        w: f32,
+        l: f32,
+        lambda: f32,
+        gamma: f32,
+        phi: f32,
+        n: f32,
+    ) {
+        let level = if ekv {
+            MosfetLevel::Ekv
+        } else {
+            MosfetLevel::Level1
+        };
+        let model = MosfetModel {
+            level,
+            vto,
+            kp,
+            w,
+            l,
+            lambda,
+            gamma,
+            phi,
+            n,
+        };
+        self.0.mosfet_set_model(element_id, model);
+    }
+
+    // >>>> オペアンプ
+
+    // 理想オペアンプを作成する
+    pub fn add_ideal_opamp(&mut self) -> usize {
+        self.0.add_ideal_opamp()
+    }
+
+    // 有限利得のオペアンプ（マクロモデル）を作成する
+    pub fn add_opamp(&mut self) -> usize {
+        self.0.add_opamp()
+    }
+
+    // オペアンプのモデルパラメータを設定する (引数は OpAmpModel のフィールドの順)
+    #[allow(clippy::too_many_arguments)]
+    pub fn opamp_set_model(
+        &mut self,
+        element_id: usize,
+        ideal: bool,
+        gain: f32,
+        gbw: f32,
+        vos: f32,
+        rail_low: f32,
+        rail_high: f32,
+        r_out: f32,
+        i_limit: f32,
+    ) -> Result<(), JsValue> {
+        let model = OpAmpModel {
+            ideal,
+            gain,
+            gbw,
+            vos,
+            rail_low,
+            rail_high,
+            r_out,
+            i_limit,
+        };
+        self.0
+            .opamp_set_model(element_id, model)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // >>>> LED
+
+    // LED を作成する
+    pub fn add_led(&mut self, color: LedColor) -> usize {
+        self.0.add_led(color)
+    }
+
+    // LED の順方向電圧, 直列抵抗, 定格電流を変化させる
+    pub fn led_set_params(&mut self, element_id: usize, threshold: f32, rs: f32, i_max: f32) {
+        self.0.led_set_params(element_id, threshold, rs, i_max);
+    }
+
     // >>>> 定常電圧源
 
     // 定常電圧源を作成する
@@ -79,6 +364,187 @@ impl Circuit {
         self.0.add_ind_voltage_src(v)
     }
 
+    // >>>> 電池
+
+    // 電池を作成する
+    pub fn add_battery(&mut self, chemistry: BatteryChemistry) -> usize {
+        self.0.add_battery(chemistry)
+    }
+
+    // 電池の容量 [mAh] と内部抵抗 [Ω] を変更する
+    pub fn battery_set_params(
+        &mut self,
+        element_id: usize,
+        capacity: f64,
+        r: f32,
+    ) -> Result<(), JsValue> {
+        self.0
+            .battery_set_params(element_id, capacity, r)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // 電池の充電率 (0.0 - 1.0) を変更する
+    pub fn battery_set_soc(&mut self, element_id: usize, soc: f32) -> Result<(), JsValue> {
+        self.0
+            .battery_set_soc(element_id, soc)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // >>>> 定常電流源
+
+    // 定常電流源を作成する
+    pub fn add_ind_current_src(&mut self, i: f32) -> usize {
+        self.0.add_ind_current_src(i)
+    }
+
+    // >>>> 電源の波形
+
+    // 一定値に戻す
+    pub fn src_set_dc(&mut self, element_id: usize, value: f32) {
+        self.0.src_set_waveform(element_id, Waveform::Dc(value));
+    }
+
+    // PULSE(V1 V2 TD TR TF PW PER)
+    //   ・波形の設定は SPICE の引数の並びのまま受け取る.
+    #[allow(clippy::too_many_arguments)]
+    pub fn src_set_pulse(
+        &mut self,
+        element_id: usize,
+        v1: f32,
+        v2: f32,
+        td: f64,
+        tr: f64,
+        tf: f64,
+        pw: f64,
+        per: f64,
+    ) {
+        let waveform = Waveform::Pulse {
+            v1,
+            v2,
+            td,
+            tr,
+            tf,
+            pw,
+            per,
+        };
+        self.0.src_set_waveform(element_id, waveform);
+    }
+
+    // SIN(VO VA FREQ TD THETA PHASE)
+    #[allow(clippy::too_many_arguments)]
+    pub fn src_set_sin(
+        &mut self,
+        element_id: usize,
+        vo: f32,
+        va: f32,
+        freq: f64,
+        td: f64,
+        theta: f64,
+        phase: f64,
+    ) {
+        let waveform = Waveform::Sin {
+            vo,
+            va,
+            freq,
+            td,
+            theta,
+            phase,
+        };
+        self.0.src_set_waveform(element_id, waveform);
+    }
+
+    // EXP(V1 V2 TD1 TAU1 TD2 TAU2)
+    #[allow(clippy::too_many_arguments)]
+    pub fn src_set_exp(
+        &mut self,
+        element_id: usize,
+        v1: f32,
+        v2: f32,
+        td1: f64,
+        tau1: f64,
+        td2: f64,
+        tau2: f64,
+    ) {
+        let waveform = Waveform::Exp {
+            v1,
+            v2,
+            td1,
+            tau1,
+            td2,
+            tau2,
+        };
+        self.0.src_set_waveform(element_id, waveform);
+    }
+
+    // PWL(T1 V1 T2 V2 ...). times と values は同じ長さにすること.
+    pub fn src_set_pwl(&mut self, element_id: usize, times: Vec<f64>, values: Vec<f32>) {
+        let points = times.into_iter().zip(values).collect();
+        self.0.src_set_waveform(element_id, Waveform::Pwl(points));
+    }
+
+    // SFFM(VO VA FC MDI FS)
+    pub fn src_set_sffm(
+        &mut self,
+        element_id: usize,
+        vo: f32,
+        va: f32,
+        fc: f64,
+        mdi: f64,
+        fs: f64,
+    ) {
+        let waveform = Waveform::Sffm {
+            vo,
+            va,
+            fc,
+            mdi,
+            fs,
+        };
+        self.0.src_set_waveform(element_id, waveform);
+    }
+
+    // 小信号解析用の振幅と位相 [度]
+    pub fn src_set_ac(&mut self, element_id: usize, magnitude: f32, phase: f32) {
+        self.0.src_set_ac(element_id, magnitude, phase);
+    }
+
+    // from < t <= to の範囲で、電源の波形が不連続に変わる時刻
+    pub fn breakpoints(&self, from: f64, to: f64) -> Vec<f64> {
+        self.0.breakpoints(from, to)
+    }
+
+    // >>>> 制御電源
+
+    // 電圧制御電圧源 (E) を作成する
+    pub fn add_vcvs(&mut self, gain: f32) -> usize {
+        self.0.add_vcvs(gain)
+    }
+
+    // 電圧制御電流源 (G) を作成する
+    pub fn add_vccs(&mut self, gain: f32) -> usize {
+        self.0.add_vccs(gain)
+    }
+
+    // 電流制御電圧源 (H) を作成する
+    //   ・制御する回路素子が存在しない場合などは例外が投げられる
+    pub fn add_ccvs(&mut self, gain: f32, ctrl_element_id: usize) -> Result<usize, JsValue> {
+        self.0
+            .add_ccvs(gain, ctrl_element_id)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // 電流制御電流源 (F) を作成する
+    //   ・制御する回路素子が存在しない場合などは例外が投げられる
+    pub fn add_cccs(&mut self, gain: f32, ctrl_element_id: usize) -> Result<usize, JsValue> {
+        self.0
+            .add_cccs(gain, ctrl_element_id)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // 制御電源の利得を変化させる
+    pub fn controlled_src_change_gain(&mut self, element_id: usize, gain: f32) {
+        self.0.controlled_src_change_gain(element_id, gain);
+    }
+
     // >>>> ArduinoUno
 
     // ArduinoUno を作成する
@@ -90,4 +556,297 @@ impl Circuit {
     pub fn arduino_uno_program(&mut self, element_id: usize, hex: String) {
         self.0.arduino_uno_program(element_id, hex);
     }
+
+    // >>>> MCU
+
+    // MCU に ELF ファイルを書き込む
+    //   ・エラーの場合は例外が投げられる
+    pub fn mcu_program_elf(&mut self, element_id: usize, elf: &[u8]) -> Result<(), JsValue> {
+        self.0
+            .mcu_program_elf(element_id, elf)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // Flash 上のバイトアドレスを関数名に変換する
+    pub fn mcu_symbolize(&self, element_id: usize, address: u32) -> Option<String> {
+        self.0.mcu_symbolize(element_id, address)
+    }
+
+    // >>>> ArduinoNano
+
+    // ArduinoNano を作成する
+    pub fn add_arduino_nano(&mut self) -> usize {
+        self.0.add_arduino_nano()
+    }
+
+    // ArduinoNano にプログラムを書き込む
+    pub fn arduino_nano_program(&mut self, element_id: usize, hex: String) {
+        self.0.arduino_nano_program(element_id, hex);
+    }
+
+    // >>>> サブサーキット
+
+    // サブサーキットを JSON で定義する
+    //   ・{"ports": ["in", "out"], "params": {"R": 1000},
+    //      "elements": [{"name": "R1", "type": "registor", "value": "R", "nodes": ["in", "out"]}]}
+    //   ・エラーの場合は例外が投げられる
+    pub fn define_subcircuit(&mut self, name: &str, json: &str) -> Result<(), JsValue> {
+        let subcircuit = Subcircuit::from_json(json).map_err(|err| JsValue::from_str(&err))?;
+        self.0.define_subcircuit(name, subcircuit);
+        Ok(())
+    }
+
+    // サブサーキットのインスタンスを作成する
+    //   ・params はパラメータを上書きする JSON ({"R": 2200}). 空文字列の場合は上書きしない.
+    //   ・エラーの場合は例外が投げられる
+    pub fn add_subcircuit(
+        &mut self,
+        instance: &str,
+        name: &str,
+        ports: Vec<usize>,
+        params: &str,
+    ) -> Result<(), JsValue> {
+        let params: BTreeMap<String, f32> = if params.is_empty() {
+            BTreeMap::new()
+        } else {
+            serde_json::from_str(params).map_err(|err| JsValue::from_str(&err.to_string()))?
+        };
+        let params: Vec<(&str, f32)> = params.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        self.0
+            .add_subcircuit(instance, name, &ports, &params)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // >>>> 名前
+
+    // 回路素子に名前を付ける
+    pub fn name_element(&mut self, element_id: usize, name: &str) {
+        self.0.name_element(element_id, name);
+    }
+
+    // ノードに名前を付ける
+    pub fn name_node(&mut self, node_id: usize, name: &str) {
+        self.0.name_node(node_id, name);
+    }
+
+    // 名前 ("X1.R2" など) から回路素子の id を求める
+    pub fn element_id(&self, name: &str) -> Option<usize> {
+        self.0.element_id(name)
+    }
+
+    // 名前 ("X1.mid" など) からノードの id を求める
+    pub fn node_id(&self, name: &str) -> Option<usize> {
+        self.0.node_id(name)
+    }
+
+    // 最後に求めた状態を、回路素子とノードの名前で表した JSON
+    pub fn named_state(&self) -> Option<String> {
+        self.0
+            .state
+            .as_ref()
+            .map(|state| serde_json::to_string(&self.0.named_state(state)).unwrap())
+    }
+
+    // >>>> モデル
+
+    // SPICE の .model 文を読み込み、読み込んだモデルの名前を JSON で返す
+    //   ・エラーの場合は例外が投げられる
+    pub fn load_models(&mut self, text: &str) -> Result<String, JsValue> {
+        self.0
+            .load_models(text)
+            .map(|names| serde_json::to_string(&names).unwrap())
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // 使えるモデルの名前を JSON で返す
+    pub fn model_names(&self) -> String {
+        serde_json::to_string(&self.0.models.names()).unwrap()
+    }
+
+    // モデル名を指定して部品を作成する
+    //   ・エラーの場合は例外が投げられる
+    pub fn add_part(&mut self, name: &str) -> Result<usize, JsValue> {
+        self.0.add_part(name).map_err(|err| JsValue::from_str(&err))
+    }
+
+    // >>>> パラメータ
+
+    // 回路素子の変更できるパラメータの一覧を JSON で返す
+    //   ・[{"name": "resistance", "unit": "Ω", "min": 0.01, "max": null}, ...]
+    pub fn params(&self, element_id: usize) -> Result<String, JsValue> {
+        self.0
+            .params(element_id)
+            .map(|params| serde_json::to_string(&params).unwrap())
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // 回路素子のパラメータの値
+    //   ・エラーの場合は例外が投げられる
+    pub fn get_param(&self, element_id: usize, name: &str) -> Result<f32, JsValue> {
+        self.0
+            .get_param(element_id, name)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // 回路素子のパラメータを名前で変更する
+    //   ・エラーの場合は例外が投げられる
+    pub fn set_param(&mut self, element_id: usize, name: &str, value: f32) -> Result<(), JsValue> {
+        self.0
+            .set_param(element_id, name, value)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // >>>> 回路の構成
+
+    // 全ての回路素子の種類、ピン、パラメータを JSON で返す
+    //   ・[{"id": 1, "kind": "registor", "name": null,
+    //       "pins": [{"name": "1", "node_id": 1}, ...], "params": {"resistance": 1000.0}}, ...]
+    pub fn elements(&self) -> String {
+        serde_json::to_string(&self.0.elements_info()).unwrap()
+    }
+
+    // 回路素子の種類、ピン、パラメータを JSON で返す
+    pub fn element(&self, element_id: usize) -> Result<String, JsValue> {
+        self.0
+            .element_info(element_id)
+            .map(|info| serde_json::to_string(&info).unwrap())
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // 全てのノードの id を JSON で返す (GND を含む)
+    pub fn nodes(&self) -> String {
+        serde_json::to_string(&self.0.nodes).unwrap()
+    }
+
+    // ノードに結合している [element_id, pin_id] の一覧を JSON で返す
+    pub fn node_elements(&self, node_id: usize) -> String {
+        serde_json::to_string(&self.0.node_elements(node_id)).unwrap()
+    }
+
+    // >>>> 動作点
+
+    // 最後に求めた状態での動作点を JSON で返す
+    //   ・エラーの場合は例外が投げられる
+    pub fn operating_point(&self) -> Result<String, JsValue> {
+        self.0
+            .operating_point()
+            .map(|op| serde_json::to_string(&op).unwrap())
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // 最後に求めた状態での動作点を表形式のテキストで返す
+    pub fn operating_point_text(&self) -> Result<String, JsValue> {
+        self.0
+            .operating_point()
+            .map(|op| op.to_string())
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // >>>> AC 解析
+
+    // 最後に求めた動作点での小信号 AC 解析の結果を JSON で返す
+    //   ・frequencies: {"list": [1000]} / {"decade": {"start": 10, "stop": 100000, "points": 10}}
+    //   ・エラーの場合は例外が投げられる
+    pub fn ac_analysis(&self, frequencies: &str) -> Result<String, JsValue> {
+        let frequencies: SweepValues =
+            serde_json::from_str(frequencies).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.0
+            .ac_analysis(&frequencies)
+            .map(|points| serde_json::to_string(&points).unwrap())
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // >>>> パラメータ掃引
+
+    // 回路素子のパラメータを掃引し、各値での解析結果を JSON で返す
+    //   ・values: {"list": [100, 220]} / {"linear": {"start": 0, "stop": 5, "step": 0.5}}
+    //             {"decade": {"start": 10, "stop": 100000, "points": 10}}
+    //   ・analysis: "operating_point" / {"transient": {"duration": 0.001}}
+    //             {"ac": {"frequencies": {"list": [1000]}}}
+    //   ・エラーの場合は例外が投げられる
+    pub fn step(
+        &mut self,
+        element_id: usize,
+        name: &str,
+        values: &str,
+        analysis: &str,
+    ) -> Result<String, JsValue> {
+        let values: SweepValues =
+            serde_json::from_str(values).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        let analysis: Analysis =
+            serde_json::from_str(analysis).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.0
+            .step(element_id, name, &values, &analysis)
+            .map(|points| serde_json::to_string(&points).unwrap())
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // >>>> モンテカルロ解析
+
+    // 回路素子のパラメータに公差を設定する
+    //   ・relative は公称値に対する割合 (±5% なら 0.05)
+    pub fn set_tolerance(
+        &mut self,
+        element_id: usize,
+        name: &str,
+        relative: f32,
+        distribution: Distribution,
+    ) -> Result<(), JsValue> {
+        let tolerance = Tolerance {
+            relative,
+            distribution,
+        };
+        self.0
+            .set_tolerance(element_id, name, tolerance)
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    pub fn clear_tolerance(&mut self, element_id: usize, name: &str) {
+        self.0.clear_tolerance(element_id, name)
+    }
+
+    // runs 回の解析を行い、結果と統計を JSON で返す
+    //   ・analysis: "operating_point" / {"transient": {"duration": 0.001}}
+    //   ・probes: [{"type": "voltage", "node_id": 1}, {"type": "current", "element_id": 2, "pin_id": 0}]
+    //   ・エラーの場合は例外が投げられる
+    pub fn monte_carlo(
+        &mut self,
+        runs: usize,
+        seed: u32,
+        analysis: &str,
+        probes: &str,
+    ) -> Result<String, JsValue> {
+        let analysis: Analysis =
+            serde_json::from_str(analysis).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        let probes: Vec<Probe> =
+            serde_json::from_str(probes).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.0
+            .monte_carlo(runs, seed as u64, &analysis, &probes)
+            .map(|result| serde_json::to_string(&result).unwrap())
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // >>>> 感度解析・ワーストケース解析
+
+    // 最後に求めた状態での各出力の、全てのパラメータに対する感度を JSON で返す
+    //   ・probes: [{"type": "voltage", "node_id": 1}, {"type": "current", "element_id": 2, "pin_id": 0}]
+    //   ・エラーの場合は例外が投げられる
+    pub fn sensitivity(&self, probes: &str) -> Result<String, JsValue> {
+        let probes: Vec<Probe> =
+            serde_json::from_str(probes).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.0
+            .sensitivity(&probes)
+            .map(|result| serde_json::to_string(&result).unwrap())
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    // 公差の範囲で各出力が最小・最大になる組み合わせを JSON で返す
+    pub fn worst_case(&mut self, probes: &str) -> Result<String, JsValue> {
+        let probes: Vec<Probe> =
+            serde_json::from_str(probes).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.0
+            .worst_case(&probes)
+            .map(|result| serde_json::to_string(&result).unwrap())
+            .map_err(|err| JsValue::from_str(&err))
+    }
 }
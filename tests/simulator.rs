@@ -1,5 +1,7 @@
+use circuit_simulator::average::*;
 use circuit_simulator::simulator::*;
 use serde_json::*;
+use std::collections::BTreeMap;
 use std::fs;
 
 #[test]
@@ -94,7 +96,8 @@ fn test_simulator_arduinouno() {
     let node0 = sim.add_node();
     let node1 = sim.add_node();
 
-    sim.connect_element_pin_node(eid0, 18, node0);
+    // D13 (PB5)
+    sim.connect_element_pin_node(eid0, 13, node0);
     sim.connect_element_pin_node(eid1, 0, node0);
     sim.connect_element_pin_node(eid1, 1, node1);
     sim.connect_element_pin_node(eid2, 0, node1);
@@ -117,3 +120,1561 @@ fn test_simulator_arduinouno() {
         };
     }
 }
+
+#[test]
+fn test_simulator_arduinouno_pin_names() {
+    let mut sim = Simulator::new();
+
+    // ArduinoUno D13 - N1 - 抵抗 - GND
+    let eid0 = sim.add_arduino_uno();
+    let eid1 = sim.add_registor(1000.0);
+    let node0 = sim.add_node();
+    let info = sim.element_info(eid0).unwrap();
+    let d13 = info.pins.iter().position(|pin| pin.name == "D13").unwrap();
+    sim.connect_element_pin_node(eid0, d13, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+
+    // L チカのプログラムは D13 (PB5) を High にする
+    let hex = fs::read_to_string(SAMPLE_FILE_NAME).unwrap();
+    sim.arduino_uno_program(eid0, hex);
+    sim.update_state().unwrap();
+    let changes = sim.run_until_change(1_000_000).unwrap();
+    assert!((changes[0].state.0[&node0] - 5.0).abs() < 1e-3);
+
+    let levels = sim.pin_levels();
+    assert!(levels[&eid0][d13]);
+    // pin_id 18 は A4 (PC4)
+    assert_eq!(info.pins[18].name, "A4");
+    assert!(!levels[&eid0][18]);
+}
+
+#[test]
+fn test_simulator_run_cycles() {
+    let mut sim = Simulator::new();
+
+    // GND - 電源 - N1 - 抵抗 - GND
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(5.0);
+    let node0 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+
+    // 状態が計算されていないと進められない
+    assert!(sim.run_cycles(1).is_err());
+
+    sim.update_state().unwrap();
+
+    // MCU が無いので状態は変化しない
+    let changes = sim.run_cycles(16).unwrap();
+    assert!(changes.is_empty());
+    assert_eq!(sim.cycle, 16);
+    assert!((sim.time() - 1e-6).abs() < 1e-12);
+
+    let changes = sim.run_until_change(10).unwrap();
+    assert!(changes.is_empty());
+    assert_eq!(sim.cycle, 26);
+}
+
+#[test]
+fn test_simulator_average() {
+    let mut sim = Simulator::new();
+
+    // GND - 電源 - N1 - 抵抗 - GND
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(5.0);
+    let node0 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+
+    sim.set_averaging_window(1e-3);
+    sim.update_state().unwrap();
+    sim.run_cycles(100).unwrap();
+
+    let average = sim.average().unwrap();
+    assert!((average.voltages[&node0] - 5.0).abs() < 1e-3);
+    assert!((average.currents[&eid1][0] - 1.0).abs() < 1e-3);
+    assert!((average.currents[&eid0][0] + 1.0).abs() < 1e-3);
+
+    // デューティ比 50% の PWM
+    let mut averager = Averager::new(100);
+    let high: BTreeMap<usize, f32> = [(1, 5.0)].iter().cloned().collect();
+    let low: BTreeMap<usize, f32> = [(1, 0.0)].iter().cloned().collect();
+    let mut levels = BTreeMap::new();
+    for cycle in (0..200).step_by(10) {
+        let is_high = cycle % 20 == 0;
+        levels.insert(1, vec![is_high]);
+        let voltages = if is_high { high.clone() } else { low.clone() };
+        averager.push(cycle, voltages, BTreeMap::new(), levels.clone());
+    }
+    let average = averager.average(200).unwrap();
+    assert!((average.voltages[&1] - 2.5).abs() < 1e-3);
+    assert!((average.duty_cycles[&1][0] - 0.5).abs() < 1e-3);
+}
+
+#[test]
+fn test_simulator_arduinonano() {
+    let mut sim = Simulator::new();
+
+    // ArduinoNano (A6) - N1 - 抵抗 - GND
+    let eid0 = sim.add_arduino_nano();
+    let eid1 = sim.add_registor(330.0);
+    let node0 = sim.add_node();
+
+    // A6 はアナログ入力専用なので出力ピンにはならない
+    sim.connect_element_pin_node(eid0, 20, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+
+    let state = sim.update_state().unwrap();
+    assert_eq!(serde_json::to_string(&state).unwrap(), r#"{"1":0.0}"#);
+    assert_eq!(sim.equation.unwrap().x.len(), 1);
+}
+
+const SAMPLE_ELF_FILE_NAME: &str = "tests/elf/led_flashing.elf";
+
+#[test]
+fn test_simulator_arduinouno_elf() {
+    let elf = fs::read(SAMPLE_ELF_FILE_NAME).unwrap();
+
+    // Flash のイメージは同じプログラムの Intel HEX と一致する
+    let program = circuit_simulator::elf::load_elf(&elf).unwrap();
+    let hex = fs::read_to_string(SAMPLE_FILE_NAME).unwrap();
+    assert_eq!(
+        program.hex.lines().collect::<Vec<_>>(),
+        hex.lines().collect::<Vec<_>>()
+    );
+    assert!(program.eeprom.is_empty());
+
+    let mut sim = Simulator::new();
+    let eid0 = sim.add_arduino_uno();
+    sim.mcu_program_elf(eid0, &elf).unwrap();
+
+    assert_eq!(sim.mcu_symbolize(eid0, 0x00), Some("__vectors".to_string()));
+    assert_eq!(
+        sim.mcu_symbolize(eid0, 0xc0),
+        Some("__init+0x8".to_string())
+    );
+    assert_eq!(sim.mcu_symbolize(eid0, 0x400), None);
+
+    assert!(sim.mcu_program_elf(eid0, b"not an elf").is_err());
+
+    // .data の初期値は LMA (.text の直後) に置かれ、SRAM 上のシンボルは含まない
+    let elf = fs::read("tests/elf/data_section.elf").unwrap();
+    let program = circuit_simulator::elf::load_elf(&elf).unwrap();
+    let image = [0x00, 0xc0, 0xff, 0xcf, b'D', b'A', b'T', b'A'];
+    assert_eq!(program.hex, circuit_simulator::elf::to_intel_hex(&image));
+    assert_eq!(program.symbols.0.len(), 1);
+    assert_eq!(program.symbols.symbolize(0x02), Some("main".to_string()));
+
+    // .eeprom は EEPROM の初期値になり、Flash には含まれない
+    let elf = fs::read("tests/elf/eeprom_section.elf").unwrap();
+    let program = circuit_simulator::elf::load_elf(&elf).unwrap();
+    assert_eq!(program.eeprom, vec![1, 2, 3, 4]);
+    assert_eq!(
+        program.hex,
+        circuit_simulator::elf::to_intel_hex(&image[..4])
+    );
+
+    //   ・EEPROM を書き込めないコアではエラーになり、以前のプログラムが残る.
+    assert!(sim.mcu_program_elf(eid0, &elf).is_err());
+    assert_eq!(sim.mcu_symbolize(eid0, 0x00), Some("__vectors".to_string()));
+}
+
+#[test]
+fn test_simulator_pushbutton() {
+    let mut sim = Simulator::new();
+
+    // GND - 電源 - N1 - 押しボタン - N2 - 抵抗 - GND
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_pushbutton();
+    let eid2 = sim.add_registor(1000.0);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid2, 0, node1);
+
+    let state = sim.update_state().unwrap();
+    assert!(state.0[&node1].abs() < 0.01);
+
+    // 押した直後に状態が変化する
+    sim.set_switch_state(eid1, true);
+    let changes = sim.run_until_change(1).unwrap();
+    assert!((changes[0].state.0[&node1] - 5.0).abs() < 0.01);
+
+    // 離すとチャタリングしながら開く
+    sim.set_switch_bounce(eid1, 0.001);
+    sim.set_switch_state(eid1, false);
+    let changes = sim.run_for(0.002).unwrap();
+    assert!(changes.len() > 2);
+    assert!(changes.last().unwrap().state.0[&node1].abs() < 0.01);
+
+    // 1ms だけ押すと、その後は自動的に離される
+    sim.set_switch_bounce(eid1, 0.0);
+    sim.press_pushbutton(eid1, 0.001).unwrap();
+    let changes = sim.run_for(0.002).unwrap();
+    assert_eq!(changes.len(), 2);
+    assert!((changes[0].state.0[&node1] - 5.0).abs() < 0.01);
+    assert!(changes[1].state.0[&node1].abs() < 0.01);
+    assert!((changes[1].time - changes[0].time - 0.001).abs() < 1e-9);
+    assert_eq!(sim.get_param(eid1, "state"), Ok(0.0));
+
+    // 押しボタン以外は押せない
+    let eid3 = sim.add_spst_switch();
+    assert!(sim.press_pushbutton(eid3, 0.001).is_err());
+    //   ・存在しない素子や、スイッチ以外の素子もエラーになる
+    assert!(sim.press_pushbutton(100, 0.001).is_err());
+    assert!(sim.press_pushbutton(eid0, 0.001).is_err());
+
+    // ON 抵抗 / OFF 抵抗は範囲外の値だとエラーで、どちらも変わらない
+    sim.set_switch_resistance(eid3, 1.0, 1e6).unwrap();
+    assert_eq!(sim.get_param(eid3, "r_on"), Ok(1.0));
+    assert!(sim.set_switch_resistance(eid3, 2.0, 0.0).is_err());
+    assert_eq!(sim.get_param(eid3, "r_on"), Ok(1.0));
+    assert_eq!(sim.get_param(eid3, "r_off"), Ok(1e6));
+    assert!(sim.set_switch_resistance(eid3, -1.0, 1e6).is_err());
+}
+
+#[test]
+fn test_simulator_potentiometer() {
+    let mut sim = Simulator::new();
+
+    // GND - 電源 - N1 - 可変抵抗器 (N2: ワイパー) - GND
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_potentiometer(10000.0);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 2.5).abs() < 0.01);
+
+    sim.potentiometer_change_position(eid1, 0.2);
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 4.0).abs() < 0.01);
+
+    // A カーブは中点で全抵抗の 10%
+    let eid2 = sim.add_log_potentiometer(10000.0);
+    let node2 = sim.add_node();
+    sim.connect_element_pin_node(eid2, 0, node0);
+    sim.connect_element_pin_node(eid2, 1, node2);
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node2] - 4.5).abs() < 0.01);
+
+    // 全抵抗値を変えてもワイパーの位置の比は変わらない. 範囲外の値はエラー.
+    sim.potentiometer_change_registance(eid1, 20000.0).unwrap();
+    assert!(sim.state.is_none());
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 4.0).abs() < 0.01);
+    assert!(sim.potentiometer_change_registance(eid1, 0.0).is_err());
+    assert_eq!(sim.get_param(eid1, "resistance"), Ok(20000.0));
+}
+
+#[test]
+fn test_simulator_controlled_src() {
+    let mut sim = Simulator::new();
+
+    // GND - 電源 1V - N1 - 抵抗 1k - GND
+    let eid0 = sim.add_ind_voltage_src(1.0);
+    let eid1 = sim.add_registor(1000.0);
+    let node0 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+
+    // E: N2 = 3 * N1
+    let eid2 = sim.add_vcvs(3.0);
+    let eid3 = sim.add_registor(1000.0);
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid2, 0, node1);
+    sim.connect_element_pin_node(eid2, 2, node0);
+    sim.connect_element_pin_node(eid3, 0, node1);
+
+    // G: 1mS * N1 の電流を N3 から引き抜いて 1k に流す => N3 = -1V
+    let eid4 = sim.add_vccs(0.001);
+    let eid5 = sim.add_registor(1000.0);
+    let node2 = sim.add_node();
+    sim.connect_element_pin_node(eid4, 0, node2);
+    sim.connect_element_pin_node(eid4, 2, node0);
+    sim.connect_element_pin_node(eid5, 0, node2);
+
+    // H: N4 = 2000 * I(電源) = -2V (電源の電流は 1mA 流れ出している)
+    let eid6 = sim.add_ccvs(2000.0, eid0).unwrap();
+    let eid7 = sim.add_registor(1000.0);
+    let node3 = sim.add_node();
+    sim.connect_element_pin_node(eid6, 0, node3);
+    sim.connect_element_pin_node(eid7, 0, node3);
+
+    // F: 10 * I(電源) = -10mA を N5 から引き抜く => N5 = 10V
+    let eid8 = sim.add_cccs(10.0, eid0).unwrap();
+    let eid9 = sim.add_registor(1000.0);
+    let node4 = sim.add_node();
+    sim.connect_element_pin_node(eid8, 0, node4);
+    sim.connect_element_pin_node(eid9, 0, node4);
+
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 3.0).abs() < 1e-3);
+    assert!((state.0[&node2] + 1.0).abs() < 1e-3);
+    assert!((state.0[&node3] + 2.0).abs() < 1e-3);
+    assert!((state.0[&node4] - 10.0).abs() < 1e-3);
+
+    // 制御する電流がない回路素子（存在しない、抵抗など）は指定できない
+    assert!(sim.add_ccvs(1.0, 99).is_err());
+    assert!(sim.add_cccs(1.0, eid1).is_err());
+    assert_eq!(sim.elements.len(), 10);
+}
+
+#[test]
+fn test_simulator_bjt() {
+    let mut sim = Simulator::new();
+
+    // エミッタ接地: 電源 5V - 抵抗 1k - C,  電源 5V - 抵抗 470k - B,  E - GND
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(1000.0);
+    let eid2 = sim.add_registor(470000.0);
+    let eid3 = sim.add_npn();
+    let vcc = sim.add_node();
+    let base = sim.add_node();
+    let collector = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, vcc);
+    sim.connect_element_pin_node(eid1, 0, vcc);
+    sim.connect_element_pin_node(eid1, 1, collector);
+    sim.connect_element_pin_node(eid2, 0, vcc);
+    sim.connect_element_pin_node(eid2, 1, base);
+    sim.connect_element_pin_node(eid3, 0, base);
+    sim.connect_element_pin_node(eid3, 1, collector);
+
+    // Ib = (5 - 0.65) / 470k = 9.25uA,  Ic = 100 * Ib
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&base] - 0.65).abs() < 0.02);
+    assert!((state.0[&collector] - 4.075).abs() < 0.02);
+
+    // エミッタ抵抗を入れるとコレクタ電流が減る
+    let model = circuit_simulator::elements::bjt::BjtModel {
+        re: 1000.0,
+        ..Default::default()
+    };
+    sim.bjt_set_model(eid3, model);
+    let state = sim.update_state().unwrap();
+    assert!(state.0[&collector] > 4.2);
+
+    // 直列抵抗の内側は内部ノードになり、src の電流としては扱わない
+    use circuit_simulator::monte_carlo::Probe;
+    let model = circuit_simulator::elements::bjt::BjtModel {
+        rb: 100.0,
+        rc: 10.0,
+        re: 1000.0,
+        ..Default::default()
+    };
+    sim.bjt_set_model(eid3, model);
+    let state = sim.update_state().unwrap();
+    let eq = sim.equation.as_ref().unwrap();
+    assert!(eq
+        .src_index
+        .keys()
+        .all(|(element_id, _)| *element_id != eid3));
+    assert_eq!(eq.internal_index.len(), 3);
+    let current = |pin_id: usize| {
+        sim.probe(&Probe::Current {
+            element_id: eid3,
+            pin_id,
+        })
+        .unwrap()
+    };
+    //   ・端子の電流は Ib, Ic, Ie の 3 つだけで、合計は 0
+    assert!((current(1) - 100.0 * current(0)).abs() < 1e-6);
+    assert!((current(1) - (5.0 - state.0[&collector]) / 1000.0).abs() < 1e-4);
+    assert!((current(0) + current(1) + current(2)).abs() < 1e-9);
+    assert!(sim
+        .probe(&Probe::Current {
+            element_id: eid3,
+            pin_id: 3,
+        })
+        .is_err());
+
+    // PNP: E - 電源 5V,  B - 抵抗 470k - GND,  C - 抵抗 1k - GND
+    let mut sim = Simulator::new();
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(1000.0);
+    let eid2 = sim.add_registor(470000.0);
+    let eid3 = sim.add_pnp();
+    let vcc = sim.add_node();
+    let base = sim.add_node();
+    let collector = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, vcc);
+    sim.connect_element_pin_node(eid3, 2, vcc);
+    sim.connect_element_pin_node(eid3, 0, base);
+    sim.connect_element_pin_node(eid3, 1, collector);
+    sim.connect_element_pin_node(eid2, 0, base);
+    sim.connect_element_pin_node(eid1, 0, collector);
+
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&base] - 4.35).abs() < 0.02);
+    assert!((state.0[&collector] - 0.925).abs() < 0.02);
+}
+
+#[test]
+fn test_simulator_mosfet() {
+    use circuit_simulator::elements::mosfet::*;
+
+    // ローサイドスイッチ: 電源 5V - 抵抗 - D,  G - ゲート電圧,  S - GND
+    let circuit = |vgs: f32, r: f32, model: MosfetModel| {
+        let mut sim = Simulator::new();
+        let eid0 = sim.add_ind_voltage_src(5.0);
+        let eid1 = sim.add_ind_voltage_src(vgs);
+        let eid2 = sim.add_registor(r);
+        let eid3 = sim.add_nmos();
+        sim.mosfet_set_model(eid3, model);
+        let vcc = sim.add_node();
+        let gate = sim.add_node();
+        let drain = sim.add_node();
+        sim.connect_element_pin_node(eid0, 0, vcc);
+        sim.connect_element_pin_node(eid1, 0, gate);
+        sim.connect_element_pin_node(eid2, 0, vcc);
+        sim.connect_element_pin_node(eid2, 1, drain);
+        sim.connect_element_pin_node(eid3, 0, drain);
+        sim.connect_element_pin_node(eid3, 1, gate);
+        sim.update_state().unwrap().0[&drain]
+    };
+
+    // ON (線形領域), OFF, 飽和領域 (Id = 0.5 / 2 * 0.5^2 * (1 + 0.01 * Vds))
+    assert!(circuit(5.0, 100.0, MosfetModel::default()) < 0.1);
+    assert!(circuit(0.0, 100.0, MosfetModel::default()) > 4.99);
+    assert!((circuit(2.0, 10.0, MosfetModel::default()) - 4.348).abs() < 0.01);
+
+    let ekv = MosfetModel {
+        level: MosfetLevel::Ekv,
+        ..Default::default()
+    };
+    assert!(circuit(5.0, 100.0, ekv.clone()) < 0.1);
+    assert!(circuit(0.0, 100.0, ekv) > 4.99);
+
+    // ハイサイドスイッチ: S - 電源 5V,  G - GND,  D - 抵抗 - GND
+    let mut sim = Simulator::new();
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(100.0);
+    let eid2 = sim.add_pmos();
+    let vcc = sim.add_node();
+    let drain = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, vcc);
+    sim.connect_element_pin_node(eid2, 2, vcc);
+    sim.connect_element_pin_node(eid2, 0, drain);
+    sim.connect_element_pin_node(eid1, 0, drain);
+    let state = sim.update_state().unwrap();
+    assert!(state.0[&drain] > 4.9);
+
+    // モデルを変えると状態は計算し直しになる
+    sim.mosfet_set_model(eid2, MosfetModel::default());
+    assert!(sim.state.is_none());
+}
+
+#[test]
+fn test_simulator_opamp() {
+    use circuit_simulator::elements::waveform::Waveform;
+    use circuit_simulator::monte_carlo::Probe;
+
+    // 非反転増幅: Vin - (+),  出力 - 10k - (-) - 10k - GND  => 利得 2
+    let amplifier = |ideal: bool, vin: f32| {
+        let mut sim = Simulator::new();
+        let eid0 = sim.add_ind_voltage_src(vin);
+        let eid1 = if ideal {
+            sim.add_ideal_opamp()
+        } else {
+            sim.add_opamp()
+        };
+        let eid2 = sim.add_registor(10000.0);
+        let eid3 = sim.add_registor(10000.0);
+        let input = sim.add_node();
+        let minus = sim.add_node();
+        let output = sim.add_node();
+        sim.connect_element_pin_node(eid0, 0, input);
+        sim.connect_element_pin_node(eid1, 0, input);
+        sim.connect_element_pin_node(eid1, 1, minus);
+        sim.connect_element_pin_node(eid1, 2, output);
+        sim.connect_element_pin_node(eid2, 0, output);
+        sim.connect_element_pin_node(eid2, 1, minus);
+        sim.connect_element_pin_node(eid3, 0, minus);
+        sim.update_state().unwrap().0[&output]
+    };
+
+    assert!((amplifier(true, 1.0) - 2.0).abs() < 1e-3);
+    assert!((amplifier(false, 1.0) - 2.0).abs() < 1e-3);
+    // 理想オペアンプはレールを無視し、マクロモデルはレールで飽和する
+    assert!((amplifier(true, 3.0) - 6.0).abs() < 1e-3);
+    assert!((amplifier(false, 3.0) - 5.0).abs() < 0.05);
+
+    // コンパレータ: (+) 2V, (-) 1V, 出力 - 10k - GND
+    let mut sim = Simulator::new();
+    let eid0 = sim.add_ind_voltage_src(2.0);
+    let eid1 = sim.add_ind_voltage_src(1.0);
+    let eid2 = sim.add_opamp();
+    let eid3 = sim.add_registor(10000.0);
+    let plus = sim.add_node();
+    let minus = sim.add_node();
+    let output = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, plus);
+    sim.connect_element_pin_node(eid1, 0, minus);
+    sim.connect_element_pin_node(eid2, 0, plus);
+    sim.connect_element_pin_node(eid2, 1, minus);
+    sim.connect_element_pin_node(eid2, 2, output);
+    sim.connect_element_pin_node(eid3, 0, output);
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&output] - 4.96).abs() < 0.01);
+
+    // 負荷を 10Ω にすると出力電流が 25mA に制限される
+    sim.registor_change_registance(eid3, 10.0);
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&output] - 0.25).abs() < 0.01);
+
+    // 出力を電源でレールの外 (10V) に固定しても収束し、出力は電流制限の 25mA を吸い込む
+    let eid4 = sim.add_ind_voltage_src(10.0);
+    sim.connect_element_pin_node(eid4, 0, output);
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&output] - 10.0).abs() < 1e-4);
+    let current = sim.probe(&Probe::Current {
+        element_id: eid2,
+        pin_id: 2,
+    });
+    assert!((current.unwrap() - 0.025).abs() < 1e-3);
+
+    //   ・下側のレールの外 (-10V) でも同様
+    sim.src_set_waveform(eid4, Waveform::Dc(-10.0));
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&output] + 10.0).abs() < 1e-4);
+    let current = sim.probe(&Probe::Current {
+        element_id: eid2,
+        pin_id: 2,
+    });
+    assert!((current.unwrap() + 0.025).abs() < 1e-3);
+
+    // 出力電圧の範囲が逆転するモデルは設定できない
+    use circuit_simulator::elements::opamp::OpAmpModel;
+    let model = OpAmpModel {
+        rail_low: 5.0,
+        rail_high: 0.0,
+        ..Default::default()
+    };
+    assert!(sim.opamp_set_model(eid2, model).is_err());
+    assert_eq!(sim.get_param(eid2, "rail_high"), Ok(5.0));
+}
+
+#[test]
+fn test_simulator_led() {
+    use circuit_simulator::elements::led::LedColor;
+
+    let mut sim = Simulator::new();
+
+    // 電源 5V - N1 - 抵抗 150Ω - N2 - 赤色 LED - GND
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(150.0);
+    let eid2 = sim.add_led(LedColor::Red);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid2, 0, node1);
+
+    // I = (5 - 1.8) / 160 = 20mA
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 2.0).abs() < 1e-3);
+    assert!((state.1[&eid2]["brightness"] - 1.0).abs() < 1e-3);
+
+    // 状態の JSON はノードの電圧だけで、出力値は含まない
+    let json = serde_json::to_string(&state).unwrap();
+    assert_eq!(json, r#"{"1":5.0,"2":2.0}"#);
+    let json = serde_json::to_string(&state.1).unwrap();
+    assert!(json.starts_with(r#"{"3":{"brightness":"#));
+    //   ・比較も JSON と同じくノードの電圧だけで行う
+    let voltages = State::new(state.0.clone());
+    assert_eq!(voltages, state);
+
+    // 抵抗を小さくすると定格電流を超える
+    sim.registor_change_registance(eid1, 50.0);
+    let state = sim.update_state().unwrap();
+    assert_eq!(state.1[&eid2]["over_current"], 1.0);
+}
+
+#[test]
+fn test_simulator_zener() {
+    let mut sim = Simulator::new();
+
+    // 電源 12V - N1 - 抵抗 1kΩ - N2 - ツェナーダイオード (5.1V, 逆向き) - GND
+    let eid0 = sim.add_ind_voltage_src(12.0);
+    let eid1 = sim.add_registor(1000.0);
+    let eid2 = sim.add_zener(5.1);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid2, 1, node1);
+
+    // 逆電圧が降伏電圧付近で一定になる
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 5.129).abs() < 1e-2);
+
+    // 電流が倍になっても電圧はほとんど変わらない
+    sim.registor_change_registance(eid1, 500.0);
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 5.163).abs() < 1e-2);
+
+    // 降伏しない通常のダイオードに戻すと、電源電圧がそのまま現れる
+    sim.diode_set_breakdown(eid2, 0.0, 0.0, 0.0);
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 12.0).abs() < 1e-2);
+}
+
+#[test]
+fn test_simulator_waveform() {
+    use circuit_simulator::elements::waveform::Waveform;
+
+    let mut sim = Simulator::new();
+
+    // パルス電源 - N1 - 抵抗 1kΩ - GND
+    //   ・1us 後に 0V -> 5V へ 1us で立ち上がり、2us 保持して 1us で立ち下がる. 周期 10us.
+    let eid0 = sim.add_ind_voltage_src(0.0);
+    let eid1 = sim.add_registor(1000.0);
+    let node0 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.src_set_waveform(
+        eid0,
+        Waveform::Pulse {
+            v1: 0.0,
+            v2: 5.0,
+            td: 1e-6,
+            tr: 1e-6,
+            tf: 1e-6,
+            pw: 2e-6,
+            per: 10e-6,
+        },
+    );
+    let breakpoints = sim.breakpoints(0.0, 13e-6);
+    let expected = [1e-6, 2e-6, 4e-6, 5e-6, 11e-6, 12e-6];
+    assert_eq!(breakpoints.len(), expected.len());
+    for (t, e) in breakpoints.iter().zip(expected.iter()) {
+        assert!((t - e).abs() < 1e-12);
+    }
+
+    let state = sim.update_state().unwrap();
+    assert_eq!(state.0[&node0], 0.0);
+
+    // 立ち上がりの途中
+    sim.run_for(1.5e-6).unwrap();
+    assert!((sim.state.as_ref().unwrap().0[&node0] - 2.5).abs() < 1e-3);
+
+    // High の間は変化しない
+    let changes = sim.run_for(2e-6).unwrap();
+    assert_eq!(changes.last().unwrap().state.0[&node0], 5.0);
+    assert_eq!(changes.last().unwrap().time, 2e-6);
+
+    // SIN: 遅延の前はオフセット、遅延の後は減衰しながら振動する
+    let sin = Waveform::Sin {
+        vo: 1.0,
+        va: 2.0,
+        freq: 1e3,
+        td: 1e-3,
+        theta: 100.0,
+        phase: 0.0,
+    };
+    assert_eq!(sin.value(0.5e-3), 1.0);
+    assert!((sin.value(1.25e-3) - 2.95062).abs() < 1e-4);
+    assert!((sin.value(1.75e-3) + 0.85549).abs() < 1e-4);
+    let sin = Waveform::Sin {
+        vo: 0.0,
+        va: 1.0,
+        freq: 1e3,
+        td: 0.0,
+        theta: 0.0,
+        phase: 90.0,
+    };
+    assert!((sin.value(0.0) - 1.0).abs() < 1e-6);
+    assert!((sin.value(0.5e-3) + 1.0).abs() < 1e-6);
+
+    // EXP: td1 から v2 へ、td2 から v1 へ指数関数的に近づく
+    let exp = Waveform::Exp {
+        v1: 0.0,
+        v2: 5.0,
+        td1: 1e-3,
+        tau1: 1e-3,
+        td2: 3e-3,
+        tau2: 2e-3,
+    };
+    assert_eq!(exp.value(0.5e-3), 0.0);
+    assert!((exp.value(2e-3) - 3.16060).abs() < 1e-4);
+    assert!((exp.value(5e-3) - 1.74782).abs() < 1e-4);
+
+    // PWL: 最初の点より前は最初の値、最後の点より後は最後の値
+    let pwl = Waveform::Pwl(vec![(1e-3, 1.0), (2e-3, 3.0), (4e-3, -1.0)]);
+    assert_eq!(pwl.value(0.0), 1.0);
+    assert_eq!(pwl.value(1e-3), 1.0);
+    assert!((pwl.value(1.5e-3) - 2.0).abs() < 1e-6);
+    assert_eq!(pwl.value(2e-3), 3.0);
+    assert!((pwl.value(3e-3) - 1.0).abs() < 1e-6);
+    assert_eq!(pwl.value(4e-3), -1.0);
+    assert_eq!(pwl.value(5e-3), -1.0);
+
+    // SFFM: vo + va * sin(2π fc t + mdi * sin(2π fs t))
+    let sffm = Waveform::Sffm {
+        vo: 1.0,
+        va: 2.0,
+        fc: 1e3,
+        mdi: 2.0,
+        fs: 100.0,
+    };
+    assert_eq!(sffm.value(0.0), 1.0);
+    assert!((sffm.value(0.25e-3) - 2.90291).abs() < 1e-4);
+    assert!((sffm.value(0.4e-3) - 1.26113).abs() < 1e-4);
+
+    // クロックの時刻と一致しないブレークポイント (1.03us = 16.48 クロック) でも
+    // 時間の刻みが区切られ、波形の頂点の状態が返る.
+    sim.src_set_waveform(
+        eid0,
+        Waveform::Pwl(vec![(4e-6, 0.0), (5.03e-6, 5.0), (6.06e-6, 0.0)]),
+    );
+    sim.update_state().unwrap();
+    let changes = sim.run_for(3e-6).unwrap();
+    let peak = changes
+        .iter()
+        .find(|change| change.time == 5.03e-6)
+        .unwrap();
+    assert_eq!(peak.state.0[&node0], 5.0);
+    assert_eq!(peak.cycle, 80);
+    //   ・時刻の順に並び、最後は指定した時間で終わる.
+    assert!(changes.windows(2).all(|w| w[0].time < w[1].time));
+    assert_eq!(sim.time(), 6.5e-6);
+
+    // SIN の値はクロックごとに少しずつ変わるが、分解能以上変化した時だけ解き直す.
+    sim.src_set_waveform(
+        eid0,
+        Waveform::Sin {
+            vo: 0.0,
+            va: 1.0,
+            freq: 1e3,
+            td: 0.0,
+            theta: 0.0,
+            phase: 0.0,
+        },
+    );
+    sim.update_state().unwrap();
+    let changes = sim.run_for(1e-4).unwrap();
+    assert!(!changes.is_empty());
+    assert!(changes.len() < 800);
+    //   ・出力の誤差は振幅の幅 (2V) の 0.1% 以内
+    let expected = (2.0 * std::f64::consts::PI * 1e3 * sim.time()).sin() as f32;
+    assert!((sim.state.as_ref().unwrap().0[&node0] - expected).abs() < 2e-3);
+
+    // 定常電流源 1mA - N1 - 抵抗 1kΩ - GND
+    let mut sim = Simulator::new();
+    let eid0 = sim.add_ind_current_src(0.001);
+    let eid1 = sim.add_registor(1000.0);
+    let node0 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 1, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node0] - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_simulator_battery() {
+    use circuit_simulator::elements::battery::BatteryChemistry;
+
+    let mut sim = Simulator::new();
+
+    // リチウムイオン電池 - N1 - 抵抗 10Ω - GND
+    let eid0 = sim.add_battery(BatteryChemistry::LiIon);
+    let eid1 = sim.add_registor(10.0);
+    let node0 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+
+    // 内部抵抗 0.05Ω の分だけ電圧が下がる
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node0] - 4.179).abs() < 1e-3);
+    assert_eq!(state.1[&eid0]["soc"], 1.0);
+    assert_eq!(state.1[&eid0]["charge"], 2600.0);
+
+    // 容量をごく小さくして 1ms 放電させる
+    //   ・0.418A * 1ms = 0.418mC は 0.001mAh (3.6mC) の 11.6%
+    sim.battery_set_params(eid0, 0.001, 0.05).unwrap();
+    sim.update_state().unwrap();
+    let changes = sim.run_for(1e-3).unwrap();
+    let state = &changes.last().unwrap().state;
+    assert!((state.1[&eid0]["soc"] - 0.884).abs() < 1e-2);
+    assert!(state.0[&node0] < 4.179 - 0.1);
+
+    // 範囲外の値はエラーで、変更しない
+    let soc = sim.get_param(eid0, "soc").unwrap();
+    assert!(sim.battery_set_soc(eid0, 1.5).is_err());
+    assert!(sim.battery_set_params(eid0, 2600.0, -1.0).is_err());
+    assert!((sim.get_param(eid0, "capacity").unwrap() - 0.001).abs() < 1e-9);
+    assert_eq!(sim.get_param(eid0, "soc"), Ok(soc));
+    sim.battery_set_soc(eid0, 0.5).unwrap();
+    assert!(sim.state.is_none());
+    assert_eq!(sim.get_param(eid0, "soc"), Ok(0.5));
+}
+
+#[test]
+fn test_simulator_subcircuit() {
+    use circuit_simulator::subcircuit::*;
+
+    let mut sim = Simulator::new();
+
+    // 分圧回路: in - R1 - out - R2 - GND
+    let divider = Subcircuit::new(&["in", "out"])
+        .param("R1", 1000.0)
+        .param("R2", 1000.0)
+        .element(
+            "R1",
+            SubElement::Registor { value: "R1".into() },
+            &["in", "out"],
+        )
+        .element(
+            "R2",
+            SubElement::Registor { value: "R2".into() },
+            &["out", "0"],
+        );
+    sim.define_subcircuit("DIVIDER", divider);
+
+    // 分圧回路を 2 段つないだもの (JSON で定義)
+    //   in - XA - mid - XB - out
+    let json = r#"{
+        "ports": ["in", "out"],
+        "params": {"R": 1000},
+        "elements": [
+            {"name": "XA", "type": "subcircuit", "subcircuit": "DIVIDER",
+             "params": {"R1": "R", "R2": "R"}, "nodes": ["in", "mid"]},
+            {"name": "XB", "type": "subcircuit", "subcircuit": "DIVIDER",
+             "params": {"R1": "R", "R2": "R"}, "nodes": ["mid", "out"]}
+        ]
+    }"#;
+    sim.define_subcircuit("LADDER", Subcircuit::from_json(json).unwrap());
+
+    // 電源 9V - N1 - X1 - N2
+    let eid0 = sim.add_ind_voltage_src(9.0);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.name_node(node0, "vin");
+    sim.name_node(node1, "vout");
+    sim.add_subcircuit("X1", "DIVIDER", &[node0, node1], &[("R2", 2000.0)])
+        .unwrap();
+
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 6.0).abs() < 1e-4);
+
+    let named = sim.named_state(&state);
+    assert_eq!(named.voltages["vin"], 9.0);
+    assert!((named.voltages["vout"] - 6.0).abs() < 1e-4);
+    assert!(sim.element_id("X1.R1").is_some());
+
+    // 入れ子のサブサーキットは階層名になる
+    let node2 = sim.add_node();
+    sim.add_subcircuit("X2", "LADDER", &[node0, node2], &[("R", 10000.0)])
+        .unwrap();
+    assert!(sim.element_id("X2.XB.R2").is_some());
+    let mid = sim.node_id("X2.mid").unwrap();
+
+    // 9V * (2/3R) / (2/3R + R) * 1/2 = 1.8V
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&mid] - 3.6).abs() < 1e-3);
+    assert!((state.0[&node2] - 1.8).abs() < 1e-3);
+
+    // 未定義のパラメータやポート数の誤り、使用済みのインスタンス名はエラーになり、回路は変更されない
+    let count = sim.elements.len();
+    assert!(sim
+        .add_subcircuit("X3", "DIVIDER", &[node0, node1], &[("R3", 1.0)])
+        .is_err());
+    assert!(sim.add_subcircuit("X3", "DIVIDER", &[node0], &[]).is_err());
+    assert!(sim
+        .add_subcircuit("X2", "DIVIDER", &[node0, node1], &[])
+        .is_err());
+    assert_eq!(sim.elements.len(), count);
+
+    // スイッチ、電池、電流制御電源も含められる
+    //   電池 (+) - V1 (0V, 電流計) - sw - out - R - GND,  H: mon = 100 * I(V1)
+    let json = r#"{
+        "ports": ["out", "mon"],
+        "elements": [
+            {"name": "B1", "type": "battery", "chemistry": "LiIon", "nodes": ["vb", "0"]},
+            {"name": "V1", "type": "ind_voltage_src", "value": 0, "nodes": ["vb", "a"]},
+            {"name": "S1", "type": "switch", "kind": "spst", "on": true, "nodes": ["a", "out"]},
+            {"name": "R1", "type": "registor", "value": 100, "nodes": ["out", "0"]},
+            {"name": "H1", "type": "ccvs", "gain": 100, "control": "V1", "nodes": ["mon", "0"]}
+        ]
+    }"#;
+    sim.define_subcircuit("CELL", Subcircuit::from_json(json).unwrap());
+    let node3 = sim.add_node();
+    let node4 = sim.add_node();
+    sim.add_subcircuit("X3", "CELL", &[node3, node4], &[])
+        .unwrap();
+    assert!(sim.element_id("X3.S1").is_some());
+
+    // 4.2V / (100 + 0.05 + 0.01)Ω = 42mA が V1 を流れる => mon = 100 * 42mA
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node3] - 4.2 * 100.0 / 100.06).abs() < 1e-3);
+    assert!((state.0[&node4] - 4.2 * 100.0 / 100.06).abs() < 1e-2);
+
+    // 制御する回路素子は先に定義した電流を持つものでなければならない
+    let json = r#"{
+        "ports": ["mon"],
+        "elements": [
+            {"name": "R1", "type": "registor", "value": 100, "nodes": ["mon", "0"]},
+            {"name": "H1", "type": "ccvs", "gain": 100, "control": "R1", "nodes": ["mon", "0"]}
+        ]
+    }"#;
+    sim.define_subcircuit("BAD", Subcircuit::from_json(json).unwrap());
+    let count = sim.elements.len();
+    assert!(sim.add_subcircuit("X4", "BAD", &[node4], &[]).is_err());
+    assert_eq!(sim.elements.len(), count);
+}
+
+#[test]
+fn test_simulator_model() {
+    use circuit_simulator::model::*;
+
+    assert_eq!(parse_number("2.52n"), Some(2.52e-9));
+    assert_eq!(parse_number("1Meg"), Some(1e6));
+    assert_eq!(parse_number("1e-3"), Some(1e-3));
+    assert_eq!(parse_number("10V"), Some(10.0));
+
+    let mut sim = Simulator::new();
+
+    // 電源 5V - N1 - 抵抗 330Ω - N2 - 赤色 LED (カタログ) - GND
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(330.0);
+    let eid2 = sim.add_part("led_red").unwrap();
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid2, 0, node1);
+
+    // I = (5 - 1.8) / 340 = 9.4mA
+    let state = sim.update_state().unwrap();
+    assert!((state.1[&eid2]["current"] - 0.00941).abs() < 1e-4);
+
+    // .model 文で定義したダイオードに置き換える
+    let text = "
+* 継続行とコメント
+.model MYDIODE D(IS=1e-14 N=1
++ RS=0.1) ; 小信号用
+";
+    assert_eq!(sim.load_models(text).unwrap(), vec!["MYDIODE"]);
+    let eid3 = sim.add_part("MyDiode").unwrap();
+    sim.connect_element_pin_node(eid2, 0, 0);
+    sim.connect_element_pin_node(eid3, 0, node1);
+
+    // 1mA - 10mA の間では 0.6V - 0.7V 程度
+    let state = sim.update_state().unwrap();
+    assert!(0.6 < state.0[&node1] && state.0[&node1] < 0.75);
+
+    assert!(sim.add_part("NOSUCHPART").is_err());
+    assert!(sim.load_models(".model X JFET(VTO=-2)").is_err());
+}
+
+#[test]
+fn test_simulator_param() {
+    let mut sim = Simulator::new();
+
+    // 電源 5V - N1 - 抵抗 1kΩ - N2 - 抵抗 1kΩ - GND
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(1000.0);
+    let eid2 = sim.add_registor(1000.0);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid2, 0, node1);
+
+    let params = sim.params(eid1).unwrap();
+    assert_eq!(params[0].name, "resistance");
+    assert_eq!(params[0].unit, "Ω");
+
+    // 回路素子の種類によらず同じ方法で変更できる
+    sim.set_param(eid1, "resistance", 4000.0).unwrap();
+    sim.set_param(eid0, "voltage", 10.0).unwrap();
+    assert_eq!(sim.get_param(eid1, "resistance"), Ok(4000.0));
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 2.0).abs() < 1e-4);
+
+    // 名前や範囲の誤り
+    assert!(sim.set_param(eid1, "capacitance", 1.0).is_err());
+    assert!(sim.set_param(eid1, "resistance", -1.0).is_err());
+    assert!(sim.set_param(100, "resistance", 1.0).is_err());
+
+    // 波形を指定した電源の voltage は波形の基準の値で、変更しても波形は残る
+    use circuit_simulator::elements::waveform::Waveform;
+    let sin = Waveform::Sin {
+        vo: 1.0,
+        va: 2.0,
+        freq: 1e3,
+        td: 0.0,
+        theta: 0.0,
+        phase: 90.0,
+    };
+    sim.src_set_waveform(eid0, sin.clone());
+    assert_eq!(sim.get_param(eid0, "voltage"), Ok(1.0));
+    sim.set_param(eid0, "voltage", 2.0).unwrap();
+    assert_eq!(sim.get_param(eid0, "voltage"), Ok(2.0));
+    assert_eq!(sim.src_waveform(eid0), sin);
+    //   ・t = 0 で 2 + 2 = 4V を 4k, 1k で分圧
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 0.8).abs() < 1e-4);
+    sim.set_param(eid0, "voltage", 1.0).unwrap();
+    let state = sim.update_state().unwrap();
+    assert!((state.0[&node1] - 0.6).abs() < 1e-4);
+}
+
+#[test]
+fn test_simulator_element_info() {
+    let mut sim = Simulator::new();
+
+    // 電源 5V - N1 - 抵抗 1kΩ - N2 - NPN (コレクタ)
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(1000.0);
+    let eid2 = sim.add_npn();
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid2, 1, node1);
+    sim.name_element(eid1, "RC");
+
+    let info = sim.element_info(eid2).unwrap();
+    assert_eq!(info.kind, "npn");
+    let pins: Vec<(&str, usize)> = info.pins.iter().map(|p| (p.name, p.node_id)).collect();
+    assert_eq!(
+        pins,
+        vec![("base", 0), ("collector", node1), ("emitter", 0)]
+    );
+    assert_eq!(info.params["bf"], 100.0);
+
+    let elements = sim.elements_info();
+    assert_eq!(elements.len(), 3);
+    assert_eq!(elements[1].name, Some("RC".to_string()));
+    assert_eq!(elements[1].params["resistance"], 1000.0);
+
+    assert_eq!(sim.node_elements(node0), vec![(eid0, 0), (eid1, 0)]);
+    assert_eq!(sim.node_elements(node1), vec![(eid1, 1), (eid2, 1)]);
+    assert!(sim.element_info(100).is_err());
+}
+
+#[test]
+fn test_simulator_operating_point() {
+    let mut sim = Simulator::new();
+    assert!(sim.operating_point().is_err());
+
+    // エミッタ接地: 電源 5V - 抵抗 1k - C,  電源 5V - 抵抗 470k - B,  E - GND
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(1000.0);
+    let eid2 = sim.add_registor(470000.0);
+    let eid3 = sim.add_npn();
+    let vcc = sim.add_node();
+    let base = sim.add_node();
+    let collector = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, vcc);
+    sim.connect_element_pin_node(eid1, 0, vcc);
+    sim.connect_element_pin_node(eid1, 1, collector);
+    sim.connect_element_pin_node(eid2, 0, vcc);
+    sim.connect_element_pin_node(eid2, 1, base);
+    sim.connect_element_pin_node(eid3, 0, base);
+    sim.connect_element_pin_node(eid3, 1, collector);
+    sim.name_element(eid3, "Q1");
+    sim.update_state().unwrap();
+
+    let op = sim.operating_point().unwrap();
+    assert_eq!(op.nodes.len(), 3);
+
+    // 抵抗は線形素子なので領域を持たない. 消費電力は V * I.
+    let r = &op.elements[1];
+    assert_eq!(r.region, None);
+    assert!((r.power - 0.925 * 0.000925).abs() < 1e-5);
+
+    // Ic = 0.925mA 程度で能動領域. gm = Ic / Vt.
+    let q = &op.elements[3];
+    let ic = q.small_signal["ic"];
+    assert_eq!(q.region, Some("active"));
+    assert!((ic - 0.000925).abs() < 1e-4);
+    assert!((q.small_signal["gm"] - ic / 0.025852).abs() < 1e-3);
+
+    // 電源は電力を供給する
+    assert!(op.elements[0].power < 0.0);
+
+    // 表形式のテキスト
+    let text = op.to_string();
+    assert!(text.contains("Q1"));
+    assert!(text.contains("active"));
+
+    // ベース抵抗を小さくすると飽和する
+    sim.registor_change_registance(eid2, 10000.0);
+    sim.update_state().unwrap();
+    let op = sim.operating_point().unwrap();
+    assert_eq!(op.elements[3].region, Some("saturation"));
+}
+
+#[test]
+fn test_simulator_step() {
+    use circuit_simulator::elements::led::LedColor;
+    use circuit_simulator::sweep::*;
+
+    let mut sim = Simulator::new();
+
+    // 電源 5V - N1 - 抵抗 - N2 - 赤色 LED - GND
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(1000.0);
+    let eid2 = sim.add_led(LedColor::Red);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid2, 0, node1);
+    sim.update_state().unwrap();
+
+    // 電流制限抵抗を選ぶ: I = (5 - 1.8) / (R + 10)
+    let values = SweepValues::List(vec![150.0, 220.0, 330.0]);
+    let points = sim
+        .step(eid1, "resistance", &values, &Analysis::OperatingPoint)
+        .unwrap();
+    assert_eq!(points.len(), 3);
+    for point in points.iter() {
+        match &point.result {
+            AnalysisResult::OperatingPoint(op) => {
+                let i = op.elements[2].small_signal["id"];
+                assert!((i - 3.2 / (point.value + 10.0)).abs() < 1e-5);
+            }
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    // 終了後は元の値に戻る
+    assert_eq!(sim.get_param(eid1, "resistance"), Ok(1000.0));
+
+    // 電源電圧の DC 掃引
+    let values = SweepValues::Linear {
+        start: 0.0,
+        stop: 5.0,
+        step: 0.5,
+    };
+    let points = sim
+        .step(eid0, "voltage", &values, &Analysis::OperatingPoint)
+        .unwrap();
+    assert_eq!(points.len(), 11);
+    assert_eq!(points[10].value, 5.0);
+
+    // 過渡解析は毎回同じ時刻から始まる
+    let points = sim
+        .step(
+            eid1,
+            "resistance",
+            &SweepValues::List(vec![100.0, 200.0]),
+            &Analysis::Transient { duration: 1e-6 },
+        )
+        .unwrap();
+    assert_eq!(points.len(), 2);
+    assert_eq!(sim.cycle, 0);
+
+    assert!(sim
+        .step(
+            eid1,
+            "resistance",
+            &SweepValues::List(vec![-1.0]),
+            &Analysis::OperatingPoint
+        )
+        .is_err());
+    assert_eq!(sim.get_param(eid1, "resistance"), Ok(1000.0));
+
+    // パルス電源の基準の値を掃引しても、終了後は元の波形に戻る
+    use circuit_simulator::elements::waveform::Waveform;
+    let pulse = Waveform::Pulse {
+        v1: 0.0,
+        v2: 5.0,
+        td: 0.0,
+        tr: 1e-6,
+        tf: 1e-6,
+        pw: 2e-6,
+        per: 10e-6,
+    };
+    sim.src_set_waveform(eid0, pulse.clone());
+    sim.update_state().unwrap();
+    let points = sim
+        .step(
+            eid0,
+            "voltage",
+            &SweepValues::List(vec![1.0, 2.0]),
+            &Analysis::Transient { duration: 2e-6 },
+        )
+        .unwrap();
+    //   ・波形全体がずれ、High は 6V, 7V になる
+    for point in points.iter() {
+        match &point.result {
+            AnalysisResult::Transient(changes) => {
+                let high = changes.last().unwrap().state.0[&node0];
+                assert!((high - (5.0 + point.value)).abs() < 1e-4);
+            }
+            _ => panic!("unexpected result"),
+        }
+    }
+    assert_eq!(sim.src_waveform(eid0), pulse);
+    assert_eq!(sim.get_param(eid0, "voltage"), Ok(0.0));
+    assert_eq!(sim.state.as_ref().unwrap().0[&node0], 0.0);
+}
+
+#[test]
+fn test_simulator_step_battery() {
+    use circuit_simulator::elements::battery::BatteryChemistry;
+    use circuit_simulator::sweep::*;
+
+    let mut sim = Simulator::new();
+
+    // リチウムイオン電池 (0.001mAh) - N1 - 抵抗 - GND
+    let eid0 = sim.add_battery(BatteryChemistry::LiIon);
+    let eid1 = sim.add_registor(10.0);
+    let node0 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.battery_set_params(eid0, 0.001, 0.05).unwrap();
+    sim.update_state().unwrap();
+
+    // 同じ値の過渡解析は、毎回満充電から始めるので同じ結果になる
+    let points = sim
+        .step(
+            eid1,
+            "resistance",
+            &SweepValues::List(vec![10.0, 10.0]),
+            &Analysis::Transient { duration: 1e-3 },
+        )
+        .unwrap();
+    let soc = |point: &SweepPoint| match &point.result {
+        AnalysisResult::Transient(changes) => changes.last().unwrap().elements[&eid0]["soc"],
+        _ => panic!("unexpected result"),
+    };
+    assert!((soc(&points[0]) - 0.884).abs() < 1e-2);
+    assert_eq!(points[0], points[1]);
+
+    // 終了後は残量も元に戻る
+    let state = sim.update_state().unwrap();
+    assert_eq!(state.1[&eid0]["soc"], 1.0);
+
+    // 内部状態を戻せない MCU を含む回路は、過渡解析の掃引ができない
+    sim.add_arduino_uno();
+    assert!(sim
+        .step(
+            eid1,
+            "resistance",
+            &SweepValues::List(vec![10.0]),
+            &Analysis::Transient { duration: 1e-6 },
+        )
+        .is_err());
+    assert!(sim
+        .step(
+            eid1,
+            "resistance",
+            &SweepValues::List(vec![10.0]),
+            &Analysis::OperatingPoint,
+        )
+        .is_ok());
+}
+
+#[test]
+fn test_simulator_monte_carlo() {
+    use circuit_simulator::monte_carlo::*;
+    use circuit_simulator::sweep::Analysis;
+
+    let mut sim = Simulator::new();
+
+    // 電源 5V - N1 - 抵抗 1k - N2 - 抵抗 1k - GND （分圧回路）
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(1000.0);
+    let eid2 = sim.add_registor(1000.0);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid2, 0, node1);
+    sim.update_state().unwrap();
+
+    let uniform = Tolerance {
+        relative: 0.05,
+        distribution: Distribution::Uniform,
+    };
+    sim.set_tolerance(eid1, "resistance", uniform).unwrap();
+    sim.set_tolerance(eid2, "resistance", uniform).unwrap();
+    assert!(sim.set_tolerance(eid1, "unknown", uniform).is_err());
+    //   ・公差は 0 以上 1 未満
+    for relative in [-0.1, 1.0, 2.0].iter() {
+        let tolerance = Tolerance {
+            relative: *relative,
+            distribution: Distribution::Uniform,
+        };
+        assert!(sim.set_tolerance(eid1, "resistance", tolerance).is_err());
+    }
+
+    let probes = [
+        Probe::Voltage { node_id: node1 },
+        Probe::Current {
+            element_id: eid1,
+            pin_id: 0,
+        },
+    ];
+    let result = sim
+        .monte_carlo(200, 1, &Analysis::OperatingPoint, &probes)
+        .unwrap();
+    assert_eq!(result.runs.len(), 200);
+    assert!((result.nominal[0] - 2.5).abs() < 1e-4);
+
+    // ±5% の抵抗 2 本: 2.5 * (1 ± 0.05) の範囲に収まる
+    let stat = &result.statistics[0];
+    assert!(2.375 < stat.min && stat.max < 2.625);
+    assert!((stat.mean - 2.5).abs() < 0.02);
+    assert!(0.0 < stat.sigma && stat.sigma < 0.1);
+    for run in result.runs.iter() {
+        let r1 = run.params[0].value;
+        let r2 = run.params[1].value;
+        assert!((950.0..=1050.0).contains(&r1));
+        assert!((run.outputs[0] - 5.0 * r2 / (r1 + r2)).abs() < 1e-3);
+    }
+
+    // 同じ seed なら同じ結果になる
+    let again = sim
+        .monte_carlo(200, 1, &Analysis::OperatingPoint, &probes)
+        .unwrap();
+    assert_eq!(result, again);
+
+    // 終了後は公称値に戻る
+    assert_eq!(sim.get_param(eid1, "resistance"), Ok(1000.0));
+
+    // 正規分布は公差を 3σ とする
+    let gaussian = Tolerance {
+        relative: 0.05,
+        distribution: Distribution::Gaussian,
+    };
+    sim.set_tolerance(eid1, "resistance", gaussian).unwrap();
+    sim.clear_tolerance(eid2, "resistance");
+    let result = sim
+        .monte_carlo(500, 2, &Analysis::OperatingPoint, &probes)
+        .unwrap();
+    let values: Vec<f32> = result.runs.iter().map(|run| run.params[0].value).collect();
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    let sigma =
+        (values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32).sqrt();
+    assert!((sigma - 1000.0 * 0.05 / 3.0).abs() < 3.0);
+
+    // 3σ を超えた値は公差の範囲に丸められる
+    let gaussian = Tolerance {
+        relative: 0.5,
+        distribution: Distribution::Gaussian,
+    };
+    sim.set_tolerance(eid1, "resistance", gaussian).unwrap();
+    let result = sim
+        .monte_carlo(2000, 3, &Analysis::OperatingPoint, &probes)
+        .unwrap();
+    let values: Vec<f32> = result.runs.iter().map(|run| run.params[0].value).collect();
+    assert!(values.iter().all(|v| (500.0..=1500.0).contains(v)));
+    assert!(values.iter().any(|v| *v == 500.0 || *v == 1500.0));
+
+    // パラメータの範囲を超える値は範囲の端に丸められる (電池の残量は 1 以下)
+    use circuit_simulator::elements::battery::BatteryChemistry;
+    let mut sim = Simulator::new();
+    let eid0 = sim.add_battery(BatteryChemistry::LiIon);
+    let eid1 = sim.add_registor(10.0);
+    let node0 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.update_state().unwrap();
+    let uniform = Tolerance {
+        relative: 0.1,
+        distribution: Distribution::Uniform,
+    };
+    sim.set_tolerance(eid0, "soc", uniform).unwrap();
+    let probes = [Probe::Voltage { node_id: node0 }];
+    let result = sim
+        .monte_carlo(50, 4, &Analysis::Transient { duration: 1e-6 }, &probes)
+        .unwrap();
+    assert_eq!(result.runs.len(), 50);
+    assert!(result.runs.iter().all(|run| run.params[0].value <= 1.0));
+    assert!(result.runs.iter().any(|run| run.params[0].value == 1.0));
+    assert_eq!(sim.get_param(eid0, "soc"), Ok(1.0));
+    assert_eq!(sim.cycle, 0);
+
+    //   ・エラーで終わった場合も、パラメータと時刻は元に戻る
+    let probes = [Probe::Current {
+        element_id: eid1,
+        pin_id: 5,
+    }];
+    assert!(sim
+        .monte_carlo(5, 4, &Analysis::Transient { duration: 1e-6 }, &probes)
+        .is_err());
+    assert_eq!(sim.get_param(eid0, "soc"), Ok(1.0));
+    assert_eq!(sim.cycle, 0);
+    assert!(sim.state.is_some());
+}
+
+#[test]
+fn test_simulator_sensitivity() {
+    use circuit_simulator::elements::led::LedColor;
+    use circuit_simulator::monte_carlo::*;
+
+    let mut sim = Simulator::new();
+
+    // 電源 5V - N1 - 抵抗 R1 1k - N2 - 抵抗 R2 3k - GND （分圧回路）
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(1000.0);
+    let eid2 = sim.add_registor(3000.0);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid2, 0, node1);
+
+    let probes = [
+        Probe::Voltage { node_id: node1 },
+        Probe::Current {
+            element_id: eid1,
+            pin_id: 0,
+        },
+    ];
+    assert!(sim.sensitivity(&probes).is_err());
+    sim.update_state().unwrap();
+
+    let result = sim.sensitivity(&probes).unwrap();
+    let derivative = |i: usize, element_id: usize, name: &str| {
+        result[i]
+            .params
+            .iter()
+            .find(|p| p.element_id == element_id && p.name == name)
+            .unwrap()
+            .derivative
+    };
+    let close = |a: f32, b: f32| (a - b).abs() < b.abs() * 0.01;
+
+    // V = E * R2 / (R1 + R2)
+    assert!((result[0].value - 3.75).abs() < 1e-4);
+    assert!(close(derivative(0, eid0, "voltage"), 0.75));
+    assert!(close(
+        derivative(0, eid1, "resistance"),
+        -5.0 * 3000.0 / 4000e3 / 4.0
+    ));
+    assert!(close(
+        derivative(0, eid2, "resistance"),
+        5.0 * 1000.0 / 4000e3 / 4.0
+    ));
+    // I = E / (R1 + R2)
+    assert!(close(derivative(1, eid0, "voltage"), 1.0 / 4000.0));
+    assert!(close(derivative(1, eid1, "resistance"), -5.0 / 16e6));
+
+    // 抵抗 ±5%: R1 を小さく、R2 を大きくすると V が最大になる
+    let tolerance = Tolerance {
+        relative: 0.05,
+        distribution: Distribution::Uniform,
+    };
+    sim.set_tolerance(eid1, "resistance", tolerance).unwrap();
+    sim.set_tolerance(eid2, "resistance", tolerance).unwrap();
+    let cases = sim.worst_case(&probes[..1]).unwrap();
+    let case = &cases[0];
+    assert_eq!(case.max.params[0].value, 950.0);
+    assert_eq!(case.max.params[1].value, 3150.0);
+    assert!((case.max.value - 5.0 * 3150.0 / 4100.0).abs() < 1e-4);
+    assert_eq!(case.min.params[0].value, 1050.0);
+    assert_eq!(case.min.params[1].value, 2850.0);
+    assert!((case.min.value - 5.0 * 2850.0 / 3900.0).abs() < 1e-4);
+    assert_eq!(sim.get_param(eid1, "resistance"), Ok(1000.0));
+
+    // 押しボタンを押している途中でも、感度解析で押す操作は取り消されない
+    //   ・スイッチの状態は離散的な値なので、感度も公差も求めない.
+    let eid3 = sim.add_pushbutton();
+    sim.connect_element_pin_node(eid3, 0, node1);
+    sim.press_pushbutton(eid3, 1e-6).unwrap();
+    sim.update_state().unwrap();
+    let result = sim.sensitivity(&probes).unwrap();
+    assert!(result[0].params.iter().all(|p| p.name != "state"));
+    assert!(result[0].params.iter().any(|p| p.name == "r_on"));
+    assert!(sim.set_tolerance(eid3, "state", tolerance).is_err());
+    sim.run_for(2e-6).unwrap();
+    assert_eq!(sim.get_param(eid3, "state"), Ok(0.0));
+
+    // 非線形素子: LED の電流 I = (5 - 1.8) / (R + 10)
+    let mut sim = Simulator::new();
+    let eid0 = sim.add_ind_voltage_src(5.0);
+    let eid1 = sim.add_registor(220.0);
+    let eid2 = sim.add_led(LedColor::Red);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid2, 0, node1);
+    sim.update_state().unwrap();
+
+    let probes = [Probe::Current {
+        element_id: eid2,
+        pin_id: 0,
+    }];
+    let result = sim.sensitivity(&probes).unwrap();
+    let d = result[0]
+        .params
+        .iter()
+        .find(|p| p.element_id == eid1)
+        .unwrap();
+    assert!((d.derivative - -3.2 / 230.0 / 230.0).abs() < 3.2 / 230.0 / 230.0 * 0.01);
+}
+
+#[test]
+fn test_simulator_ac_analysis() {
+    use circuit_simulator::sweep::*;
+
+    let mut sim = Simulator::new();
+
+    // 電源 2.5V (AC 1V) - N1 - オペアンプ (+),  出力 N2 - (-) のボルテージフォロワ
+    let eid0 = sim.add_ind_voltage_src(2.5);
+    let eid1 = sim.add_opamp();
+    let eid2 = sim.add_registor(10000.0);
+    let node0 = sim.add_node();
+    let node1 = sim.add_node();
+    sim.connect_element_pin_node(eid0, 0, node0);
+    sim.connect_element_pin_node(eid1, 0, node0);
+    sim.connect_element_pin_node(eid1, 1, node1);
+    sim.connect_element_pin_node(eid1, 2, node1);
+    sim.connect_element_pin_node(eid2, 0, node1);
+    sim.src_set_ac(eid0, 1.0, 0.0);
+
+    assert!(sim.ac_analysis(&SweepValues::List(vec![1.0])).is_err());
+    sim.update_state().unwrap();
+
+    // 利得帯域幅積 1MHz: 低い周波数では 1 倍、1MHz で -3dB, 位相 -45°
+    let points = sim
+        .ac_analysis(&SweepValues::List(vec![1000.0, 1_000_000.0]))
+        .unwrap();
+    assert!((points[0].nodes[&node0].magnitude - 1.0).abs() < 1e-6);
+    assert!((points[0].nodes[&node1].magnitude - 1.0).abs() < 1e-3);
+    assert!((points[1].nodes[&node1].magnitude - 0.5f32.sqrt()).abs() < 1e-2);
+    assert!((points[1].nodes[&node1].phase + 45.0).abs() < 1.0);
+
+    // 掃引の解析としても使える
+    let analysis = Analysis::Ac {
+        frequencies: SweepValues::List(vec![1_000_000.0]),
+    };
+    let points = sim
+        .step(
+            eid1,
+            "gbw",
+            &SweepValues::List(vec![2_000_000.0]),
+            &analysis,
+        )
+        .unwrap();
+    match &points[0].result {
+        AnalysisResult::Ac(ac) => {
+            // |1 / (1 + j / 2)| = 0.894
+            assert!((ac[0].nodes[&node1].magnitude - 0.894).abs() < 1e-2)
+        }
+        _ => panic!("unexpected result"),
+    }
+}